@@ -0,0 +1,474 @@
+use crate::instance::Instance;
+use crate::spatial::SpatialGrid;
+use cgmath::{InnerSpace, Vector3, Zero};
+use egui::{Grid, Slider, Ui};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+/// A group of boids where every member is linked to the rest through a chain
+/// of neighbours closer than [`Schools::link_distance`].
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    /// Stays with a school from frame to frame, unlike its index, which
+    /// changes whenever schools swap places by size
+    pub label: usize,
+    pub size: usize,
+    pub centroid: Vector3<f32>,
+    pub mean_velocity: Vector3<f32>,
+    /// Distance from the centroid to the furthest member
+    pub extent: f32,
+}
+
+/// Splits the flock into schools every step.
+pub struct Schools {
+    pub link_distance: f32,
+    /// Cluster id of every boid, indexing into `clusters`
+    pub ids: Vec<usize>,
+    /// Clusters sorted by size, largest first
+    pub clusters: Vec<Cluster>,
+}
+
+impl Schools {
+    pub fn new() -> Self {
+        Self {
+            link_distance: 4.0,
+            ids: Vec::new(),
+            clusters: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, instances: &[Instance]) {
+        let (ids, mut clusters) = find_clusters(instances, self.link_distance);
+        if self.ids.len() == ids.len() {
+            let previous = self
+                .ids
+                .iter()
+                .map(|&id| self.clusters[id].label)
+                .collect::<Vec<_>>();
+            relabel(&ids, &mut clusters, &previous);
+        }
+        self.ids = ids;
+        self.clusters = clusters;
+    }
+
+    /// Label of the school the boid at `index` is in, see [`Cluster::label`]
+    pub fn label(&self, index: usize) -> Option<usize> {
+        let id = *self.ids.get(index)?;
+        Some(self.clusters[id].label)
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui) {
+        ui.add(Slider::new(&mut self.link_distance, 0.5..=20.0).text("Link distance"));
+        ui.label(format!("{} schools", self.clusters.len()));
+        ui.separator();
+
+        Grid::new("schools_grid").striped(true).show(ui, |ui| {
+            ui.label("#");
+            ui.label("Size");
+            ui.label("Centroid");
+            ui.label("Speed");
+            ui.label("Extent");
+            ui.end_row();
+
+            for cluster in &self.clusters {
+                let [r, g, b] = cluster_color(cluster.label).map(|c| (c * 255.0) as u8);
+                ui.colored_label(egui::Color32::from_rgb(r, g, b), cluster.label.to_string());
+                ui.label(cluster.size.to_string());
+                ui.label(format!(
+                    "{:.1}, {:.1}, {:.1}",
+                    cluster.centroid.x, cluster.centroid.y, cluster.centroid.z
+                ));
+                ui.label(format!("{:.2}", cluster.mean_velocity.magnitude()));
+                ui.label(format!("{:.2}", cluster.extent));
+                ui.end_row();
+            }
+        });
+    }
+}
+
+/// Connected components of the graph linking every pair of boids closer
+/// than `link_distance`. Returns the cluster id of every boid along with the
+/// clusters, sorted so that id 0 is the largest school.
+pub fn find_clusters(instances: &[Instance], link_distance: f32) -> (Vec<usize>, Vec<Cluster>) {
+    let grid = SpatialGrid::new(link_distance, instances.iter().map(|x| x.position));
+
+    let mut parents = (0..instances.len()).collect::<Vec<_>>();
+    for (i, instance) in instances.iter().enumerate() {
+        grid.for_each_within(instance.position, link_distance, |j| {
+            if j > i {
+                union(&mut parents, i, j);
+            }
+        });
+    }
+
+    // Group members by root
+    let mut members: Vec<Vec<usize>> = Vec::new();
+    let mut root_to_group = vec![usize::MAX; instances.len()];
+    for i in 0..instances.len() {
+        let root = find(&mut parents, i);
+        if root_to_group[root] == usize::MAX {
+            root_to_group[root] = members.len();
+            members.push(Vec::new());
+        }
+        members[root_to_group[root]].push(i);
+    }
+    members.sort_by_key(|group| std::cmp::Reverse(group.len()));
+
+    let mut ids = vec![0; instances.len()];
+    let clusters = members
+        .iter()
+        .enumerate()
+        .map(|(id, group)| {
+            let n = group.len() as f32;
+            let mut centroid = Vector3::zero();
+            let mut mean_velocity = Vector3::zero();
+            for &i in group {
+                ids[i] = id;
+                centroid += instances[i].position;
+                mean_velocity += instances[i].velocity;
+            }
+            centroid /= n;
+            mean_velocity /= n;
+
+            let extent = group
+                .iter()
+                .map(|&i| (instances[i].position - centroid).magnitude())
+                .fold(0.0, f32::max);
+
+            Cluster {
+                label: id,
+                size: group.len(),
+                centroid,
+                mean_velocity,
+                extent,
+            }
+        })
+        .collect();
+
+    (ids, clusters)
+}
+
+/// Gives every cluster the label of the previous school it shares the most
+/// boids with, largest clusters first, given the label every boid had on
+/// the previous frame. Clusters without a match get the smallest free label.
+fn relabel(ids: &[usize], clusters: &mut [Cluster], previous: &[usize]) {
+    // Boids shared by every pair of current cluster and previous label
+    let mut pairs = ids
+        .iter()
+        .copied()
+        .zip(previous.iter().copied())
+        .collect::<Vec<_>>();
+    pairs.sort_unstable();
+    let mut overlaps: Vec<Vec<(usize, usize)>> = vec![Vec::new(); clusters.len()];
+    let mut start = 0;
+    while start < pairs.len() {
+        let (id, label) = pairs[start];
+        let end = start
+            + pairs[start..]
+                .iter()
+                .take_while(|x| **x == pairs[start])
+                .count();
+        overlaps[id].push((label, end - start));
+        start = end;
+    }
+
+    let mut taken = HashSet::new();
+    let mut unmatched = Vec::new();
+    for (id, cluster) in clusters.iter_mut().enumerate() {
+        let best = overlaps[id]
+            .iter()
+            .filter(|(label, _)| !taken.contains(label))
+            .max_by_key(|&&(label, shared)| (shared, Reverse(label)));
+        match best {
+            Some(&(label, _)) => {
+                cluster.label = label;
+                taken.insert(label);
+            }
+            None => unmatched.push(id),
+        }
+    }
+    let mut free = (0..).filter(|label| !taken.contains(label));
+    for id in unmatched {
+        clusters[id].label = free.next().unwrap();
+    }
+}
+
+/// Standard collective order parameters of the whole flock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OrderParameters {
+    /// Length of the mean heading, 1 when every boid swims the same way
+    pub polarization: f32,
+    /// Normalised angular momentum about the centroid, 1 for a perfect mill
+    pub milling: f32,
+    /// Mean distance from every boid to its nearest neighbour
+    pub nearest_neighbour: f32,
+    /// Distance from the centroid to the furthest boid
+    pub extent: f32,
+}
+
+/// Collective state read off the order parameters, after Tunstrøm et al. (2013).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Swarm,
+    School,
+    Mill,
+    Transitional,
+}
+
+impl OrderParameters {
+    pub fn measure(instances: &[Instance]) -> Self {
+        if instances.is_empty() {
+            return Self::default();
+        }
+        let n = instances.len() as f32;
+
+        let centroid = instances.iter().map(|x| x.position).sum::<Vector3<f32>>() / n;
+
+        let mut heading_sum = Vector3::zero();
+        let mut momentum_sum = Vector3::zero();
+        for instance in instances {
+            let heading = normalize_or_zero(instance.velocity);
+            let radial = normalize_or_zero(instance.position - centroid);
+            heading_sum += heading;
+            momentum_sum += radial.cross(heading);
+        }
+
+        let extent = instances
+            .iter()
+            .map(|x| (x.position - centroid).magnitude())
+            .fold(0.0, f32::max);
+
+        // Cells about as wide as the typical spacing, so most fish find their
+        // nearest neighbour in the first few cells around them
+        let spacing = 2.0 * extent / n.cbrt();
+        let grid = SpatialGrid::new(spacing, instances.iter().map(|x| x.position));
+        let nearest_neighbour = (0..instances.len())
+            .filter_map(|i| grid.nearest_distance(i, 2.0 * extent))
+            .sum::<f32>()
+            / n;
+
+        Self {
+            polarization: (heading_sum / n).magnitude(),
+            milling: (momentum_sum / n).magnitude(),
+            nearest_neighbour,
+            extent,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        match (self.polarization, self.milling) {
+            (p, m) if p > 0.65 && m < 0.35 => Phase::School,
+            (p, m) if m > 0.65 && p < 0.35 => Phase::Mill,
+            (p, m) if p < 0.35 && m < 0.35 => Phase::Swarm,
+            _ => Phase::Transitional,
+        }
+    }
+
+    pub(crate) fn ui(&self, ui: &mut Ui) {
+        Grid::new("order_parameters_grid").show(ui, |ui| {
+            ui.label("Polarization");
+            ui.label(format!("{:.3}", self.polarization));
+            ui.end_row();
+            ui.label("Milling");
+            ui.label(format!("{:.3}", self.milling));
+            ui.end_row();
+            ui.label("Nearest neighbour");
+            ui.label(format!("{:.2}", self.nearest_neighbour));
+            ui.end_row();
+            ui.label("Extent");
+            ui.label(format!("{:.2}", self.extent));
+            ui.end_row();
+            ui.label("Phase");
+            ui.label(format!("{:?}", self.phase()));
+            ui.end_row();
+        });
+    }
+}
+
+fn normalize_or_zero(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+/// Well separated colour for a cluster id, stepping the hue by the golden angle.
+pub fn cluster_color(id: usize) -> [f32; 3] {
+    let hue = (id as f32 * 0.618_034).fract();
+    hsv_to_rgb(hue, 0.75, 0.95)
+}
+
+pub(crate) fn hsv_to_rgb(h: f32, s: f32, v: f32) -> [f32; 3] {
+    let h = h.rem_euclid(1.0) * 6.0;
+    let c = v * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = v - c;
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let a = find(parents, a);
+    let b = find(parents, b);
+    if a != b {
+        parents[b] = a;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fish(x: f32, y: f32, z: f32) -> Instance {
+        Instance::new(Vector3::new(x, y, z), Vector3::unit_x(), 0)
+    }
+
+    #[test]
+    fn separated_groups_are_separate_schools() {
+        let instances = [
+            fish(0.0, 0.0, 0.0),
+            fish(50.0, 0.0, 0.0),
+            fish(1.0, 0.0, 0.0),
+            fish(51.0, 0.0, 0.0),
+            fish(2.0, 0.0, 0.0),
+        ];
+        let (ids, clusters) = find_clusters(&instances, 1.5);
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(ids, [0, 1, 0, 1, 0]);
+        assert_eq!(clusters[0].size, 3);
+        assert_eq!(clusters[1].size, 2);
+        assert!((clusters[0].centroid - Vector3::new(1.0, 0.0, 0.0)).magnitude() < 1e-5);
+        assert!((clusters[1].centroid - Vector3::new(50.5, 0.0, 0.0)).magnitude() < 1e-5);
+        assert!((clusters[0].extent - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn school_labels_survive_size_changes() {
+        let mut schools = Schools::new();
+        schools.link_distance = 1.5;
+        let mut instances = vec![
+            fish(0.0, 0.0, 0.0),
+            fish(1.0, 0.0, 0.0),
+            fish(2.0, 0.0, 0.0),
+            fish(50.0, 0.0, 0.0),
+            fish(51.0, 0.0, 0.0),
+        ];
+        schools.update(&instances);
+        let (left, right) = (schools.label(0).unwrap(), schools.label(3).unwrap());
+        assert_ne!(left, right);
+
+        // One fish swims over, so the right school is now the largest
+        instances[2] = fish(52.0, 0.0, 0.0);
+        schools.update(&instances);
+        assert_eq!(schools.clusters[0].size, 3);
+        assert_eq!(schools.label(0), Some(left));
+        assert_eq!(schools.label(3), Some(right));
+        assert_eq!(schools.label(2), Some(right));
+
+        // A fish leaving on its own gets a new label
+        instances[1] = fish(-50.0, 0.0, 0.0);
+        schools.update(&instances);
+        let labels = (0..5)
+            .map(|i| schools.label(i).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(labels[3], right);
+        assert_ne!(labels[0], labels[1]);
+        assert!([left, right].contains(&labels[0]) != [left, right].contains(&labels[1]));
+    }
+
+    #[test]
+    fn polarized_flock() {
+        let instances = (0..20)
+            .map(|i| {
+                let position = Vector3::new((i % 5) as f32, (i / 5) as f32, 0.0);
+                Instance::new(position, Vector3::new(0.0, 0.0, 2.0), 0)
+            })
+            .collect::<Vec<_>>();
+        let order = OrderParameters::measure(&instances);
+
+        assert!((order.polarization - 1.0).abs() < 1e-5);
+        assert!(order.milling < 1e-5);
+        assert!((order.nearest_neighbour - 1.0).abs() < 1e-5);
+        assert_eq!(order.phase(), Phase::School);
+    }
+
+    #[test]
+    fn milling_ring() {
+        let n = 36;
+        let instances = (0..n)
+            .map(|i| {
+                let angle = i as f32 / n as f32 * std::f32::consts::TAU;
+                let (sin, cos) = angle.sin_cos();
+                let position = Vector3::new(10.0 * cos, 0.0, 10.0 * sin);
+                Instance::new(position, Vector3::new(-sin, 0.0, cos), 0)
+            })
+            .collect::<Vec<_>>();
+        let order = OrderParameters::measure(&instances);
+
+        assert!(order.polarization < 1e-5);
+        assert!((order.milling - 1.0).abs() < 1e-5);
+        assert!((order.extent - 10.0).abs() < 1e-4);
+        // Chord between neighbouring fish on the ring
+        let chord = 20.0 * (std::f32::consts::PI / n as f32).sin();
+        assert!((order.nearest_neighbour - chord).abs() < 1e-4);
+        assert_eq!(order.phase(), Phase::Mill);
+    }
+
+    #[test]
+    fn nearest_neighbour_matches_brute_force() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand_chacha::ChaCha12Rng::seed_from_u64(7);
+        let mut instances = (0..200)
+            .map(|_| fish(rng.gen(), rng.gen(), rng.gen()))
+            .map(|mut x| {
+                x.position *= 40.0;
+                x
+            })
+            .collect::<Vec<_>>();
+        // A straggler far from the rest
+        instances.push(fish(500.0, 0.0, 0.0));
+
+        let brute_force = instances
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                instances
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, b)| (a.position - b.position).magnitude())
+                    .fold(f32::INFINITY, f32::min)
+            })
+            .sum::<f32>()
+            / instances.len() as f32;
+        let order = OrderParameters::measure(&instances);
+        assert!((order.nearest_neighbour - brute_force).abs() < 1e-3);
+    }
+
+    #[test]
+    fn chains_link_distant_ends() {
+        let instances = (0..10)
+            .map(|i| fish(i as f32, 0.0, 0.0))
+            .collect::<Vec<_>>();
+        let (ids, clusters) = find_clusters(&instances, 1.1);
+        assert_eq!(clusters.len(), 1);
+        assert!(ids.iter().all(|&id| id == 0));
+    }
+}
@@ -0,0 +1,30 @@
+use boids::{FlockArgs, ReportArgs};
+use clap::Parser;
+
+/// Runs the fish simulation without a window or GPU and reports
+/// statistics of the flock's order parameters
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    #[command(flatten)]
+    flock: FlockArgs,
+    /// Simulation steps to run, or --duration seconds' worth
+    #[arg(short, long, required_unless_present = "duration")]
+    steps: Option<u64>,
+    #[command(flatten)]
+    report: ReportArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+    let options = args.flock.options(args.steps);
+    if let Err(e) = options.overrides.validate() {
+        eprintln!("error: {:#}", e);
+        std::process::exit(2);
+    }
+
+    if let Err(e) = boids::run_headless(options, args.report.into()) {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
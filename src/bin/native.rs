@@ -1,3 +1,172 @@
+use boids::{Export, FlockArgs, Glyphs, Output, ReportArgs, Terminal, TintMode};
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+
+/// Simulates a school of fish in an aquarium
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    #[command(flatten)]
+    flock: FlockArgs,
+
+    /// Window width in logical pixels, or frame width when exporting
+    #[arg(long)]
+    width: Option<u32>,
+    /// Window height in logical pixels, or frame height when exporting
+    #[arg(long)]
+    height: Option<u32>,
+    /// Samples per pixel, 1 to turn multisampling off
+    #[arg(long)]
+    msaa: Option<u32>,
+    /// How frames are presented to the window
+    #[arg(long, value_enum)]
+    present_mode: Option<PresentMode>,
+    /// Use the graphics adapter whose name contains this
+    #[arg(long)]
+    adapter: Option<String>,
+    /// Use wgpu's software fallback adapter
+    #[arg(long, conflicts_with = "adapter")]
+    fallback_adapter: bool,
+
+    /// Simulate without a window and print statistics of the order
+    /// parameters. Needs --duration or --frames.
+    #[arg(long)]
+    headless: bool,
+    /// Render without a window into numbered PNGs in this directory, at
+    /// --fps frames per simulated second. Needs --duration or --frames.
+    #[arg(long, conflicts_with = "headless")]
+    png: Option<PathBuf>,
+    /// Render without a window into a YUV4MPEG2 video at this path, or
+    /// stdout for -, at --fps. Needs --duration or --frames.
+    #[arg(long, conflicts_with_all = ["headless", "png"])]
+    y4m: Option<PathBuf>,
+    /// Frames per second of simulated time when rendering without a
+    /// window, 30 by default and dividing 60 evenly, or redraws per second
+    /// in the terminal, 15 by default
+    #[arg(long)]
+    fps: Option<f32>,
+    /// Draw the tank as text in the terminal, without a window or GPU
+    #[arg(long, conflicts_with_all = ["headless", "png", "y4m"])]
+    terminal: bool,
+    /// Characters to draw the terminal view with
+    #[arg(long, value_enum, default_value_t = GlyphsArg::Braille)]
+    glyphs: GlyphsArg,
+    /// What the colour of every fish shows in the terminal view
+    #[arg(long, value_enum, default_value_t = Colour::Random)]
+    colour: Colour,
+    /// Width of the terminal view in characters, $COLUMNS or 80 by default
+    #[arg(long)]
+    columns: Option<usize>,
+    /// Height of the terminal view in characters, $LINES or 24 by default
+    #[arg(long)]
+    rows: Option<usize>,
+    /// Stop after this many frames, or steps when headless
+    #[arg(long)]
+    frames: Option<u64>,
+    #[command(flatten, next_help_heading = "With --headless")]
+    report: ReportArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PresentMode {
+    AutoVsync,
+    AutoNoVsync,
+    Fifo,
+    FifoRelaxed,
+    Immediate,
+    Mailbox,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::AutoVsync => wgpu::PresentMode::AutoVsync,
+            PresentMode::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::FifoRelaxed => wgpu::PresentMode::FifoRelaxed,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GlyphsArg {
+    Braille,
+    Blocks,
+}
+
+impl From<GlyphsArg> for Glyphs {
+    fn from(glyphs: GlyphsArg) -> Self {
+        match glyphs {
+            GlyphsArg::Braille => Glyphs::Braille,
+            GlyphsArg::Blocks => Glyphs::Blocks,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Colour {
+    Random,
+    Species,
+    Speed,
+    Heading,
+    Density,
+    Cluster,
+    Neighbourhood,
+}
+
+impl From<Colour> for TintMode {
+    fn from(colour: Colour) -> Self {
+        match colour {
+            Colour::Random => TintMode::Random,
+            Colour::Species => TintMode::Species,
+            Colour::Speed => TintMode::Speed,
+            Colour::Heading => TintMode::Heading,
+            Colour::Density => TintMode::Density,
+            Colour::Cluster => TintMode::Cluster,
+            Colour::Neighbourhood => TintMode::Neighbourhood,
+        }
+    }
+}
+
 fn main() {
-    pollster::block_on(boids::run());
+    let args = Args::parse();
+    let mut options = args.flock.options(args.frames);
+    options.overrides.width = args.width;
+    options.overrides.height = args.height;
+    options.overrides.msaa_samples = args.msaa;
+    options.present_mode = args.present_mode.map(Into::into);
+    options.adapter = args.adapter;
+    options.fallback_adapter = args.fallback_adapter;
+    if let Err(e) = options.overrides.validate() {
+        eprintln!("error: {:#}", e);
+        std::process::exit(2);
+    }
+
+    let result = if args.headless {
+        boids::run_headless(options, args.report.into())
+    } else if args.terminal {
+        let default = Terminal::default();
+        let terminal = Terminal {
+            columns: args.columns.unwrap_or(default.columns),
+            rows: args.rows.unwrap_or(default.rows),
+            glyphs: args.glyphs.into(),
+            colour: args.colour.into(),
+            fps: args.fps.unwrap_or(default.fps),
+        };
+        boids::run_terminal(options, terminal)
+    } else if let Some(output) = args.png.map(Output::Png).or(args.y4m.map(Output::Y4m)) {
+        let export = Export {
+            output,
+            fps: args.fps.unwrap_or(30.0),
+        };
+        pollster::block_on(boids::export_frames(options, export))
+    } else {
+        pollster::block_on(boids::run_with(options))
+    };
+    if let Err(e) = result {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
 }
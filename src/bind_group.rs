@@ -29,7 +29,7 @@ impl<'a> CompactBindGroupDescriptor<'a> {
             })
             .collect::<Vec<BindGroupLayoutEntry>>()
     }
-    fn to_bind_group_entry(&self) -> Vec<BindGroupEntry> {
+    fn to_bind_group_entry(&self) -> Vec<BindGroupEntry<'_>> {
         self.entries
             .iter()
             .map(|x| BindGroupEntry {
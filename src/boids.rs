@@ -1,96 +1,119 @@
-use crate::instance::Instance;
-use cgmath::*;
-use rand::distributions::{Distribution, Standard};
-use rand::Rng;
-use std::mem::MaybeUninit;
-use std::ops::Range;
-use wgpu::util::DeviceExt;
-use wgpu::{BindGroupLayout, Buffer, BufferUsages, Device, Queue};
+use crate::flock::Flock;
+use crate::instance::{Instance, InstanceRaw};
+use crate::tint::Tinting;
+use std::mem::size_of;
+use wgpu::{BindGroupLayout, BindGroupLayoutDescriptor, Buffer, BufferUsages, Device, Queue};
 
-const AQUARIUM_RADIUS: f32 = 20.0;
-const AQUARIUM_SIZE: Range<f32> = -AQUARIUM_RADIUS..AQUARIUM_RADIUS;
+pub(crate) const AQUARIUM_RADIUS: f32 = 20.0;
+/// Height of the tank floor, matching the bottom of aquarium.obj
+pub(crate) const AQUARIUM_FLOOR: f32 = -AQUARIUM_RADIUS;
+/// Height of the water surface, matching the top of aquarium.obj
+pub(crate) const AQUARIUM_SURFACE: f32 = AQUARIUM_RADIUS;
 pub const NUM_INSTANCES: usize = 50;
 
+/// The flock together with the GPU buffers it is drawn from
 pub struct Boids {
-    pub instances: [Instance; NUM_INSTANCES],
+    pub flock: Flock,
+    pub tinting: Tinting,
     pub buffer: Buffer,
+    pub tint_buffer: Buffer,
+    pub bind_group_layout: BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
+    /// Number of fish the buffers have room for
+    capacity: usize,
 }
 
 impl Boids {
-    pub fn new(device: &Device, layout: &BindGroupLayout) -> Self {
-        let mut rng = rand::thread_rng();
-
-        let instances = unsafe {
-            let mut array = MaybeUninit::<[Instance; NUM_INSTANCES]>::uninit();
-            for x in array.assume_init_mut() {
-                *x = rng.gen();
-            }
-            array.assume_init()
-        };
-
-        let tints = unsafe {
-            let mut array = MaybeUninit::<[[f32; 3]; NUM_INSTANCES]>::uninit();
-            for x in array.assume_init_mut() {
-                *x = [rng.gen(), rng.gen(), rng.gen()]
-            }
-            array.assume_init()
-        };
-
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("tint_buffer"),
-            contents: bytemuck::cast_slice(&tints),
-            usage: BufferUsages::STORAGE,
-        });
-
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("tints_bind_group"),
-            layout,
-            entries: &[wgpu::BindGroupEntry {
+    pub fn new(device: &Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
-                resource: buffer.as_entire_binding(),
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             }],
+            label: Some("tints_bind_group_layout"),
         });
-
-        let raw_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("instance_buffer"),
-            contents: bytemuck::cast_slice(&raw_data),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-        });
+        let flock = Flock::new();
+        let capacity = flock.instances.len();
+        let (buffer, tint_buffer, bind_group) =
+            create_buffers(device, &bind_group_layout, capacity);
 
         Self {
-            instances,
+            flock,
+            tinting: Tinting::new(),
             buffer,
+            tint_buffer,
+            bind_group_layout,
             bind_group,
+            capacity,
         }
     }
 
-    pub fn update(&mut self, queue: &Queue) {
-        // Run boids simulation
-        //
-        // TODO: Octree search
+    pub fn update(&mut self, device: &Device, queue: &Queue, delta: f32) {
+        self.flock.advance(delta);
+        self.upload(device, queue);
+    }
+
+    /// Writes the flock as it is now to the GPU buffers
+    pub fn upload(&mut self, device: &Device, queue: &Queue) {
+        let flock = &self.flock;
+        if flock.instances.len() > self.capacity {
+            self.capacity = flock.instances.len().next_power_of_two();
+            (self.buffer, self.tint_buffer, self.bind_group) =
+                create_buffers(device, &self.bind_group_layout, self.capacity);
+        }
 
         // Write data to buffer
-        let raw_data = self
+        let raw_data = flock
             .instances
             .iter()
             .map(Instance::to_raw)
             .collect::<Vec<_>>();
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&raw_data));
+
+        let tints = self.tinting.colors(
+            &flock.instances,
+            &flock.tints,
+            &flock.schools,
+            &flock.simulation,
+        );
+        queue.write_buffer(&self.tint_buffer, 0, bytemuck::cast_slice(&tints));
     }
 }
 
-impl Distribution<Instance> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Instance {
-        let position = Vector3 {
-            x: rng.gen_range(AQUARIUM_SIZE),
-            y: rng.gen_range(AQUARIUM_SIZE),
-            z: rng.gen_range(AQUARIUM_SIZE),
-        };
-
-        let rotation = Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0));
-
-        Instance { position, rotation }
-    }
+/// Instance and tint buffers with room for `capacity` fish, and the bind
+/// group exposing the tints to the fish shader
+fn create_buffers(
+    device: &Device,
+    layout: &BindGroupLayout,
+    capacity: usize,
+) -> (Buffer, Buffer, wgpu::BindGroup) {
+    // Empty storage buffers can't be bound
+    let capacity = capacity.max(1);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("instance_buffer"),
+        size: (capacity * size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let tint_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("tint_buffer"),
+        size: (capacity * size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("tints_bind_group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: tint_buffer.as_entire_binding(),
+        }],
+    });
+    (buffer, tint_buffer, bind_group)
 }
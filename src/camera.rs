@@ -19,16 +19,46 @@ pub struct Camera {
 }
 
 impl Camera {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+    fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    pub(crate) fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        OPENGL_TO_WGPU_MATRIX * proj * self.build_view_matrix()
+    }
+
+    /// The perspective view with the eye moved to the origin, for drawing
+    /// things infinitely far away
+    pub(crate) fn build_rotation_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let mut view = self.build_view_matrix();
+        view.w = cgmath::Vector4::unit_w();
         let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
+
+    /// Looks the same way as the perspective view, but without
+    /// foreshortening, showing `half_height` world units above and below
+    /// the target
+    pub(crate) fn build_orthographic_matrix(&self, half_height: f32) -> cgmath::Matrix4<f32> {
+        let half_width = half_height * self.aspect;
+        let proj = cgmath::ortho(
+            -half_width,
+            half_width,
+            -half_height,
+            half_height,
+            self.znear,
+            self.zfar,
+        );
+        OPENGL_TO_WGPU_MATRIX * proj * self.build_view_matrix()
+    }
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
+    /// Eye position, padded to a `vec4` for the shaders' lighting
+    view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
 }
 
@@ -36,11 +66,13 @@ impl CameraUniform {
     pub(crate) fn new() -> Self {
         use cgmath::SquareMatrix;
         Self {
+            view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
     pub(crate) fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_position = camera.eye.to_homogeneous().into();
         self.view_proj = camera.build_view_projection_matrix().into();
     }
 }
@@ -1,5 +1,5 @@
 use crate::camera::Camera;
-use egui::{Context, PointerButton};
+use egui::Context;
 use winit::event::{MouseScrollDelta, WindowEvent};
 
 pub(crate) struct CameraController {
@@ -7,7 +7,6 @@ pub(crate) struct CameraController {
     scroll_speed: f32,
     scroll_delta: f32,
     drag_speed: f32,
-    last_drag: f32,
 }
 
 impl CameraController {
@@ -17,7 +16,6 @@ impl CameraController {
             scroll_speed: 6.0,
             scroll_delta: 0.0,
             drag_speed: 0.025,
-            last_drag: 0.0,
         }
     }
 
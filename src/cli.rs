@@ -0,0 +1,111 @@
+use crate::headless::Report;
+use crate::scenario::Overrides;
+use crate::svg::{Figure, Projection};
+use crate::{Limit, Options, Preset, SimulationModel};
+use clap::Args;
+use std::path::PathBuf;
+
+/// Command-line arguments shared by the binaries for setting up the flock
+#[derive(Debug, Args)]
+pub struct FlockArgs {
+    /// TOML scenario to start from, reloaded whenever it changes while a
+    /// window is open
+    pub scenario: Option<PathBuf>,
+
+    /// Starting parameters: tight-school, loose-shoal, milling-torus,
+    /// murmuration or panicked-scatter
+    #[arg(long)]
+    pub preset: Option<Preset>,
+    /// Simulation model: reynolds, vicsek or couzin, after any preset
+    #[arg(long)]
+    pub model: Option<SimulationModel>,
+    /// Number of fish to spawn
+    #[arg(short = 'n', long)]
+    pub count: Option<usize>,
+    /// Seed for the simulation's random numbers
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Record every step to this file, binary for .boids and CSV otherwise
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Stop after this many seconds of simulated time
+    #[arg(long)]
+    pub duration: Option<f32>,
+}
+
+impl FlockArgs {
+    /// Options for a run that also stops after `frames` frames, or steps
+    /// when there is no window
+    pub fn options(self, frames: Option<u64>) -> Options {
+        Options {
+            scenario: self.scenario,
+            overrides: Overrides {
+                preset: self.preset,
+                model: self.model,
+                count: self.count,
+                seed: self.seed,
+                ..Default::default()
+            },
+            record: self.record,
+            limit: Limit {
+                duration: self.duration,
+                frames,
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Command-line arguments for what a run without a window writes out
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    /// Steps at the start left out of the statistics
+    #[arg(long, default_value_t = 0)]
+    pub warmup: u64,
+    /// Write the summary statistics to this file as JSON instead of
+    /// printing them
+    #[arg(long)]
+    pub summary: Option<PathBuf>,
+    /// Write the order parameters to this file as CSV, or to stdout for -,
+    /// which prints the summary to stderr instead
+    #[arg(long)]
+    pub series: Option<PathBuf>,
+    /// Steps between rows of the series
+    #[arg(long, default_value_t = 1)]
+    pub interval: u64,
+
+    /// Draw the flock as it is at the end into this SVG file
+    #[arg(long)]
+    pub svg: Option<PathBuf>,
+    /// Which way the SVG looks at the tank
+    #[arg(long, value_enum, default_value_t = Projection::Camera)]
+    pub view: Projection,
+    /// Width of the SVG in pixels
+    #[arg(long, default_value_t = 800.0)]
+    pub svg_width: f32,
+    /// Draw every fish's velocity as an arrow in the SVG
+    #[arg(long)]
+    pub arrows: bool,
+    /// Seconds of history drawn behind every fish in the SVG
+    #[arg(long, default_value_t = 0.0)]
+    pub trail: f32,
+}
+
+impl From<ReportArgs> for Report {
+    fn from(args: ReportArgs) -> Self {
+        Report {
+            warmup: args.warmup,
+            summary: args.summary,
+            series: args.series,
+            interval: args.interval,
+            svg: args.svg,
+            figure: Figure {
+                projection: args.view,
+                width: args.svg_width,
+                arrows: args.arrows,
+                trail: args.trail,
+            },
+        }
+    }
+}
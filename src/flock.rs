@@ -0,0 +1,333 @@
+use crate::analysis::{OrderParameters, Schools};
+use crate::history::History;
+use crate::instance::Instance;
+use crate::recording::Recorder;
+use crate::replay::Replay;
+use crate::simulation::{Simulation, TIME_STEP};
+use crate::spawn::{InitialBoid, Spawner};
+use egui::{Slider, Ui};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use std::ops::RangeInclusive;
+
+/// Seconds of simulated time kept for rewinding
+const HISTORY_DURATION: f32 = 30.0;
+
+/// Everything about the fish that doesn't live on the GPU, so the
+/// simulation can also run without a window
+pub struct Flock {
+    pub instances: Vec<Instance>,
+    /// Tint every fish was spawned with, shown in the random tint mode
+    pub tints: Vec<[f32; 4]>,
+    pub simulation: Simulation,
+    /// Settings the flock is spawned with
+    pub spawner: Spawner,
+    pub schools: Schools,
+    /// Order parameters measured on the last update
+    pub order: OrderParameters,
+    pub clock: Clock,
+    pub history: History,
+    /// Simulation steps taken since the flock was spawned
+    pub steps: u64,
+    /// Trajectory recording that every step is written to, if any
+    pub recorder: Option<Recorder>,
+    /// Error that stopped the last recording, waiting to be shown
+    pub(crate) recorder_error: Option<anyhow::Error>,
+    /// Recording driving the boids instead of the simulation, if any
+    pub replay: Option<Replay>,
+    /// Randomness used by the simulation, kept so snapshots can restore it
+    pub(crate) rng: ChaCha12Rng,
+    /// Scaled time not yet consumed by a simulation step
+    accumulator: f32,
+}
+
+/// Pausing, single stepping and speeding up the simulation
+pub struct Clock {
+    pub paused: bool,
+    /// Multiplier applied to wall-clock time, between 0.1 and 10
+    pub time_scale: f32,
+    /// Steps to take while paused, queued by [`Clock::single_step`]
+    pending_steps: u32,
+}
+
+impl Clock {
+    pub const TIME_SCALE: RangeInclusive<f32> = 0.1..=10.0;
+
+    pub fn new() -> Self {
+        Self {
+            paused: false,
+            time_scale: 1.0,
+            pending_steps: 0,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Pauses the simulation and advances it by exactly one step
+    pub fn single_step(&mut self) {
+        self.paused = true;
+        self.pending_steps += 1;
+    }
+
+    /// Multiplies the time scale by `factor`, keeping it within range
+    pub fn scale_by(&mut self, factor: f32) {
+        self.time_scale =
+            (self.time_scale * factor).clamp(*Self::TIME_SCALE.start(), *Self::TIME_SCALE.end());
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui) {
+        let label = if self.paused {
+            "▶ Resume"
+        } else {
+            "⏸ Pause"
+        };
+        if ui.button(label).on_hover_text("Space").clicked() {
+            self.toggle_pause();
+        }
+        if ui.button("Step").on_hover_text(".").clicked() {
+            self.single_step();
+        }
+        ui.add(
+            Slider::new(&mut self.time_scale, Self::TIME_SCALE)
+                .logarithmic(true)
+                .suffix("x")
+                .text("Speed"),
+        )
+        .on_hover_text("- / +");
+    }
+}
+
+impl Flock {
+    pub fn new() -> Self {
+        let mut rng = ChaCha12Rng::from_entropy();
+        let simulation = Simulation::default();
+        let spawner = Spawner::default();
+        let initial = spawner.generate(simulation.species.len(), &mut rng);
+
+        let mut flock = Self {
+            instances: Vec::new(),
+            tints: Vec::new(),
+            simulation,
+            spawner,
+            schools: Schools::new(),
+            order: OrderParameters::default(),
+            clock: Clock::new(),
+            history: History::new(HISTORY_DURATION),
+            steps: 0,
+            recorder: None,
+            recorder_error: None,
+            replay: None,
+            rng,
+            accumulator: 0.0,
+        };
+        flock.respawn(&initial);
+        flock
+    }
+
+    /// Replaces the whole flock and starts the simulation over from step 0
+    pub fn respawn(&mut self, initial: &[InitialBoid]) {
+        let last_species = self.simulation.species.len().saturating_sub(1);
+        self.instances = initial
+            .iter()
+            .map(|boid| {
+                Instance::new(
+                    boid.position(),
+                    boid.velocity(),
+                    boid.species.min(last_species),
+                )
+            })
+            .collect();
+
+        let rng = &mut self.rng;
+        self.tints = initial
+            .iter()
+            .map(|boid| match boid.tint {
+                Some([r, g, b]) => [r, g, b, 0.0],
+                None => random_tint(rng),
+            })
+            .collect();
+
+        self.restart(0);
+    }
+
+    /// Starts the timeline over from the flock as it is now, stopping any
+    /// replay and forgetting the history
+    pub(crate) fn restart(&mut self, step: u64) {
+        self.interrupt_recording("the flock was replaced");
+        self.replay = None;
+        self.steps = step;
+        self.accumulator = 0.0;
+        self.history.clear();
+        self.history.record(step, &self.instances);
+        self.analyse();
+    }
+
+    /// Moves the flock on by `delta` seconds of wall-clock time, from the
+    /// replay if one is loaded and otherwise by simulating it
+    pub fn advance(&mut self, delta: f32) {
+        if self.replay.is_some() {
+            self.interrupt_recording("a recording is being replayed");
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.advance(delta);
+            replay.apply(&mut self.instances);
+            self.steps = replay.step();
+            self.analyse();
+        } else {
+            self.simulate(delta);
+        }
+
+        // Replays, snapshots and scrubbing can all change the flock size
+        let rng = &mut self.rng;
+        self.tints
+            .resize_with(self.instances.len(), || random_tint(rng));
+    }
+
+    fn simulate(&mut self, delta: f32) {
+        // Stepping from a scrubbed frame continues the simulation from there
+        if self.history.is_scrubbing() && (!self.clock.paused || self.clock.pending_steps > 0) {
+            self.history.resume();
+        }
+
+        // Run boids simulation at a fixed rate, dropping time if we fall
+        // too far behind instead of spiralling.
+        let steps = self.steps;
+        if self.clock.paused {
+            self.accumulator = 0.0;
+            for _ in 0..self.clock.pending_steps {
+                self.step();
+            }
+            self.clock.pending_steps = 0;
+        } else {
+            let max_lag = TIME_STEP * 10.0 * self.clock.time_scale.max(1.0);
+            self.accumulator = (self.accumulator + delta * self.clock.time_scale).min(max_lag);
+            while self.accumulator >= TIME_STEP {
+                self.step();
+                self.accumulator -= TIME_STEP;
+            }
+        }
+
+        // Stepping analyses the flock already, but scrubbing or loading a
+        // snapshot may have changed it without a step.
+        if self.steps == steps {
+            self.analyse();
+        }
+    }
+
+    /// Takes one simulation step, recording it if a recording is running
+    pub fn step(&mut self) {
+        self.simulation.step(&mut self.instances, &mut self.rng);
+        self.steps += 1;
+        self.analyse();
+        self.history.record(self.steps, &self.instances);
+
+        let recorded = self.recorder.as_ref().and_then(Recorder::last_step);
+        if recorded.is_some_and(|x| x >= self.steps) {
+            self.interrupt_recording("the flock was rewound");
+        }
+        if let Some(recorder) = &mut self.recorder {
+            let result = recorder.record(self.steps, &self.instances, &self.schools, &self.order);
+            if let Err(e) = result {
+                log::error!("Recording stopped: {:#}", e);
+                self.recorder = None;
+                self.recorder_error = Some(e);
+            }
+        }
+    }
+
+    /// Stops the recording, if any, writing out everything still buffered
+    pub fn stop_recording(&mut self) -> anyhow::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Ends the recording, if any, before the flock jumps somewhere its
+    /// steps can't carry on from, leaving the reason as the recorder error
+    fn interrupt_recording(&mut self, reason: &str) {
+        if let Some(recorder) = self.recorder.take() {
+            let steps = recorder.steps();
+            self.recorder_error = Some(match recorder.finish() {
+                Ok(()) => anyhow::anyhow!("Stopped after {} steps because {}", steps, reason),
+                Err(e) => e,
+            });
+        }
+    }
+
+    fn analyse(&mut self) {
+        self.schools.update(&self.instances);
+        self.order = OrderParameters::measure(&self.instances);
+    }
+
+    /// Clock controls followed by the rewind timeline
+    pub(crate) fn timeline_ui(&mut self, ui: &mut Ui) {
+        if self.replay.is_some() {
+            ui.label("Replaying a recording");
+            return;
+        }
+
+        self.clock.ui(ui);
+        ui.separator();
+        if let Some(index) = self.history.ui(ui) {
+            self.rewind(index);
+        }
+    }
+
+    /// Pauses and shows the frame at `index` in the history. Stepping on
+    /// from there forgets the frames after it.
+    pub fn rewind(&mut self, index: usize) {
+        if let Some(frame) = self.history.scrub(index) {
+            self.clock.paused = true;
+            self.steps = frame.step;
+            frame.restore(&mut self.instances);
+        }
+    }
+}
+
+fn random_tint<R: Rng + ?Sized>(rng: &mut R) -> [f32; 4] {
+    [rng.gen(), rng.gen(), rng.gen(), 0.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recording::{Recording, RecordingFormat};
+
+    #[test]
+    fn rewinding_stops_the_recording() {
+        for format in RecordingFormat::ALL {
+            let path = std::env::temp_dir()
+                .join(format!("flock-rewind-{}", std::process::id()))
+                .with_extension(format.extension());
+            let mut flock = Flock::new();
+            flock.recorder = Some(Recorder::create(&path, format, flock.instances.len()).unwrap());
+            for _ in 0..10 {
+                flock.step();
+            }
+            flock.rewind(3);
+            assert_eq!(flock.steps, 3);
+            for _ in 0..5 {
+                flock.step();
+            }
+            assert!(flock.recorder.is_none());
+            let error = flock.recorder_error.take().unwrap().to_string();
+            assert!(error.contains("rewound"), "{}", error);
+
+            let recording = Recording::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            if format == RecordingFormat::Csv {
+                std::fs::remove_file(path.with_extension("order.csv")).unwrap();
+            }
+            let steps = recording
+                .unwrap()
+                .frames
+                .iter()
+                .map(|x| x.step)
+                .collect::<Vec<_>>();
+            assert_eq!(steps, (1..=10).collect::<Vec<_>>());
+        }
+    }
+}
@@ -1,27 +1,24 @@
-use crate::bind_group::{create_bind_group, CompactBindGroupDescriptor, CompactBindGroupEntry};
-use crate::boids::{Boids, NUM_INSTANCES};
-use crate::camera::{Camera, CameraUniform};
 use crate::camera_controller::CameraController;
-use crate::instance::InstanceRaw;
-use crate::mipmaps::generate_mipmaps;
-use crate::model::{DrawModel, Model, Vertex};
-use crate::resources::load_model;
-use crate::texture::Texture;
-use egui::{
-    Align, CentralPanel, Color32, FontDefinitions, Frame, Layout, Margin, Slider, TopBottomPanel,
-};
+use crate::preset::Preset;
+use crate::recording::{Recorder, RecordingFormat, RecordingPanel};
+use crate::replay::ReplayPanel;
+use crate::scenario::{Scenario, ScenarioPanel};
+use crate::scene::Scene;
+use crate::snapshot::SnapshotPanel;
+use crate::spawn::SpawnPanel;
+use crate::svg::FigurePanel;
+use crate::Options;
+use anyhow::Context;
+use egui::{Align, CentralPanel, Color32, FontDefinitions, Frame, Layout, Margin, TopBottomPanel};
 use egui_wgpu_backend::{RenderPass, ScreenDescriptor};
 use egui_winit_platform::{Platform, PlatformDescriptor};
 use fps_counter::FPSCounter;
 use instant::Instant;
-use log::{debug, trace};
+use log::{debug, trace, warn};
 use std::time::Duration;
-use wgpu::util::DeviceExt;
-use wgpu::{
-    BindGroupLayoutDescriptor, Device, Queue, Surface, SurfaceConfiguration, TextureFormat,
-};
-use winit::dpi::PhysicalSize;
-use winit::event::{Event, WindowEvent};
+use wgpu::{Device, PresentMode, Queue, Surface, SurfaceConfiguration, TextureFormat};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::window::Window;
 
 pub(crate) struct State {
@@ -35,38 +32,36 @@ pub(crate) struct State {
     egui_platform: Platform,
     egui_render_pass: RenderPass,
 
-    camera: Camera,
-    camera_uniform: CameraUniform,
     camera_controller: CameraController,
+    scene: Scene,
 
-    fish_model: Model,
-    aquarium_model: Model,
-
-    boids: Boids,
-
-    depth_texture: Texture,
-    multisampled_framebuffer: Texture,
-
-    camera_buffer: wgpu::Buffer,
-
-    camera_bind_group: wgpu::BindGroup,
-
-    fish_pipeline: wgpu::RenderPipeline,
-    aquarium_pipeline: wgpu::RenderPipeline,
+    snapshots: SnapshotPanel,
+    figures: FigurePanel,
+    recordings: RecordingPanel,
+    replays: ReplayPanel,
+    spawns: SpawnPanel,
+    scenarios: ScenarioPanel,
 
     fps: FPSCounter,
 }
-const MSAA_SAMPLE_COUNT: u32 = 4;
+/// Default samples per pixel, see [`RenderSettings`](crate::scenario::RenderSettings)
+pub(crate) const MSAA_SAMPLE_COUNT: u32 = 4;
 
 impl State {
     // Creating some of the wgpu types requires async code
-    pub(crate) async fn new(window: Window) -> Self {
+    pub(crate) async fn new(
+        window: Window,
+        scenario: Scenario,
+        scenarios: ScenarioPanel,
+        options: &Options,
+    ) -> anyhow::Result<Self> {
         // --- Init ---
         trace!("Starting graphics state creation");
         let timer = Instant::now();
         let size = window.inner_size();
 
-        let (device, queue, config, surface, format) = configure_surface(&window, size).await;
+        let (device, queue, config, surface, format) =
+            configure_surface(&window, size, options).await?;
 
         // --- UI ---
         let egui_platform = Platform::new(PlatformDescriptor {
@@ -78,196 +73,49 @@ impl State {
         });
         let egui_render_pass = RenderPass::new(&device, format, 1);
 
-        // --- Textures ---
-        trace!("Loading textures");
-        let depth_texture =
-            Texture::create_depth_texture(&device, &config, "depth_texture", MSAA_SAMPLE_COUNT);
-        let multisampled_framebuffer =
-            Texture::create_msfb_texture(&device, &config, "mssa_texture", MSAA_SAMPLE_COUNT);
-
-        // let diffuse_texture = load_texture("dom.png", &device, &queue).await.unwrap();
-
-        // --- Camera ---
-        let camera = Camera {
-            // position the camera one unit up and 2 units back
-            // +z is out of the screen
-            eye: (0.0, 10.0, 20.0).into(),
-            // have it look at the origin
-            target: (0.0, 0.0, 0.0).into(),
-            // which way is "up"
-            up: cgmath::Vector3::unit_y(),
-            aspect: config.width as f32 / config.height as f32,
-            fovy: 60.0,
-            znear: 0.1,
-            zfar: 100.0,
-        };
-        let camera_controller = CameraController::new();
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera);
-
-        // --- Buffers ---
-        trace!("Creating buffers");
-        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("camera_buffer"),
-            contents: bytemuck::cast_slice(&[camera_uniform]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
-        // --- Bind Groups ---
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
-
-        let boids_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::FRAGMENT,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
-            label: Some("tints_bind_group_layout"),
-        });
-
-        let (camera_bind_group, camera_bind_group_layout) = create_bind_group(
+        let mut scene = Scene::new(
             &device,
-            CompactBindGroupDescriptor {
-                label: Some("camera_bind_group"),
-                entries: &[CompactBindGroupEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    resource: camera_buffer.as_entire_binding(),
-                    count: None,
-                }],
-            },
-        );
-
-        // --- Load models ---
-        let fish_model = load_model("fish.obj", &device, &queue, &texture_bind_group_layout)
-            .await
-            .unwrap();
-        let aquarium_model =
-            load_model("aquarium.obj", &device, &queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
-
-        let boids = Boids::new(&device, &boids_bind_group_layout);
-
-        // --- Render Pipeline ---
-        trace!("Initializing render pipeline");
-        let fish_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("fish_pipeline_layout"),
-            bind_group_layouts: &[
-                &camera_bind_group_layout,
-                &texture_bind_group_layout,
-                &boids_bind_group_layout,
-            ],
-            push_constant_ranges: &[],
-        });
-        let aquarium_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("aquarium_pipeline_layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &texture_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-
-        let fish_pipeline = {
-            let shader = wgpu::include_wgsl!("shaders/fish.wgsl");
-            create_render_pipeline(
-                &device,
-                &fish_pipeline_layout,
-                config.format,
-                Some(Texture::DEPTH_FORMAT),
-                &[Vertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
-        };
-        let aquarium_pipeline = {
-            let shader = wgpu::include_wgsl!("shaders/aquarium.wgsl");
-            create_render_pipeline(
-                &device,
-                &aquarium_pipeline_layout,
-                config.format,
-                Some(Texture::DEPTH_FORMAT),
-                &[Vertex::desc()],
-                shader,
-            )
-        };
+            &queue,
+            format,
+            (config.width, config.height),
+            &scenario,
+        )
+        .await?;
+        let mut scenarios = scenarios;
+        if let Err(e) = scenario.apply(&mut scene.boids.flock, None) {
+            scenarios.set_error(e);
+        }
+        scenarios.applied(scenario);
+        if let Some(path) = &options.record {
+            let flock = &mut scene.boids.flock;
+            let format = RecordingFormat::from_path(path);
+            flock.recorder = Some(Recorder::create(path, format, flock.instances.len())?);
+        }
 
         debug!(
             "Graphics state creation finished in {:.2?}",
             timer.elapsed()
         );
 
-        // === Generate mip maps ===
-
-        trace!("Generating mip maps...");
-        let timer = Instant::now();
-
-        let textures: Vec<&Texture> = [&fish_model, &aquarium_model]
-            .iter()
-            .flat_map(|model| {
-                model
-                    .materials
-                    .iter()
-                    .map(|material| &material.diffuse_texture)
-            })
-            .collect();
-        let command_buf = generate_mipmaps(&device, &texture_bind_group_layout, &textures);
-        queue.submit([command_buf]);
-
-        debug!("Mip maps generated in {:.2?}", timer.elapsed());
-
-        Self {
+        Ok(Self {
             window,
             surface,
             device,
             queue,
             config,
             size,
-            fish_pipeline,
-            aquarium_pipeline,
-            camera_buffer,
-            fish_model,
-            aquarium_model,
-            camera,
-            camera_uniform,
-            camera_bind_group,
-            camera_controller,
+            camera_controller: CameraController::new(),
+            scene,
             fps: FPSCounter::new(),
             egui_platform,
             egui_render_pass,
-            depth_texture,
-            multisampled_framebuffer,
-            boids,
-        }
+            snapshots: SnapshotPanel::new(),
+            figures: FigurePanel::new(),
+            recordings: RecordingPanel::new(),
+            replays: ReplayPanel::new(),
+            spawns: SpawnPanel::new(),
+            scenarios,
+        })
     }
 
     pub fn resize(&mut self, new_size: PhysicalSize<u32>) {
@@ -277,28 +125,89 @@ impl State {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
 
-            self.depth_texture = Texture::create_depth_texture(
-                &self.device,
-                &self.config,
-                "depth_texture",
-                MSAA_SAMPLE_COUNT,
-            );
-            self.multisampled_framebuffer = Texture::create_msfb_texture(
-                &self.device,
-                &self.config,
-                "mssa_texture",
-                MSAA_SAMPLE_COUNT,
-            );
-
+            self.scene
+                .resize(&self.device, (new_size.width, new_size.height));
             self.surface.configure(&self.device, &self.config);
         }
     }
 
+    /// Applies a reloaded scenario, only touching what changed since the
+    /// last one so that editing a parameter doesn't reset everything
+    fn apply_scenario(&mut self, scenario: Scenario) {
+        let previous = self.scenarios.applied(scenario.clone());
+        if let Err(e) = scenario.apply(&mut self.scene.boids.flock, previous.as_ref()) {
+            self.scenarios.set_error(e);
+        }
+
+        let render = &scenario.render;
+        if previous.as_ref().map(|x| &x.render) != Some(render) {
+            self.window
+                .set_inner_size(LogicalSize::new(render.width, render.height));
+        }
+        self.scene
+            .set_msaa_samples(&self.device, render.msaa_samples);
+        self.scene.skybox.sky = render.sky;
+
+        if previous.as_ref().map(|x| &x.camera) != Some(&scenario.camera) {
+            scenario.camera.apply(&mut self.scene.camera);
+        }
+        if previous.as_ref().map(|x| &x.lighting) != Some(&scenario.lighting) {
+            self.scene.lighting = scenario.lighting.clone();
+        }
+    }
+
+    /// The scenario as currently set up, including edits made in the UI
+    fn current_scenario(&self) -> Scenario {
+        let mut scenario = self.scenarios.current().cloned().unwrap_or_default();
+        scenario.flock = self.scene.boids.flock.spawner.clone();
+        scenario.simulation = self.scene.boids.flock.simulation.clone();
+        scenario.render.msaa_samples = self.scene.msaa_samples();
+        scenario.render.sky = self.scene.skybox.sky;
+        scenario.lighting = self.scene.lighting.clone();
+        let size = self.size.to_logical::<u32>(self.window.scale_factor());
+        scenario.render.width = size.width;
+        scenario.render.height = size.height;
+        let camera = &mut scenario.camera;
+        let current = &self.scene.camera;
+        camera.position = current.eye.into();
+        camera.target = current.target.into();
+        camera.fovy = current.fovy;
+        camera.znear = current.znear;
+        camera.zfar = current.zfar;
+        scenario
+    }
+
     pub(crate) fn ui_handle_event<T>(&mut self, event: &Event<T>) {
         self.egui_platform.handle_event(event)
     }
 
     pub(crate) fn input(&mut self, event: &WindowEvent) -> bool {
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(key),
+                    ..
+                },
+            ..
+        } = event
+        {
+            if self.egui_platform.context().wants_keyboard_input() {
+                return false;
+            }
+            let clock = &mut self.scene.boids.flock.clock;
+            match key {
+                VirtualKeyCode::Space => clock.toggle_pause(),
+                VirtualKeyCode::Period => clock.single_step(),
+                VirtualKeyCode::Equals | VirtualKeyCode::Plus | VirtualKeyCode::NumpadAdd => {
+                    clock.scale_by(2.0)
+                }
+                VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => clock.scale_by(0.5),
+                _ => return false,
+            }
+            return true;
+        }
+
         self.camera_controller.process_events(event)
     }
 
@@ -319,20 +228,24 @@ impl State {
         //         });
         // }
 
-        self.boids.update(&self.queue);
+        if let Some(scenario) = self.scenarios.poll() {
+            self.apply_scenario(scenario);
+        }
+
+        self.scene
+            .boids
+            .update(&self.device, &self.queue, delta as f32);
+        if let Some(e) = self.scene.boids.flock.recorder_error.take() {
+            self.recordings.set_error(e);
+        }
 
         self.camera_controller.update_camera(
-            &mut self.camera,
+            &mut self.scene.camera,
             delta as f32,
             self.egui_platform.context(),
         );
-        self.camera_uniform.update_view_proj(&self.camera);
-
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
+        self.scene.write_camera(&self.queue);
+        self.scene.write_lighting(&self.queue);
     }
 
     pub(crate) fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -346,39 +259,7 @@ impl State {
                     label: Some("render_encoder"),
                 });
 
-        let mut render_pass = render_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("render_pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &self.multisampled_framebuffer.view,
-                resolve_target: Some(&view),
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                    store: true,
-                },
-            })],
-            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.depth_texture.view,
-                depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
-                    store: true,
-                }),
-                stencil_ops: None,
-            }),
-        });
-
-        render_pass.set_pipeline(&self.aquarium_pipeline);
-        render_pass.draw_model_instanced(&self.aquarium_model, 0..1, &self.camera_bind_group);
-
-        render_pass.set_vertex_buffer(1, self.boids.buffer.slice(..));
-        render_pass.set_bind_group(2, &self.boids.bind_group, &[]);
-        render_pass.set_pipeline(&self.fish_pipeline);
-        render_pass.draw_model_instanced(
-            &self.fish_model,
-            0..NUM_INSTANCES as u32,
-            &self.camera_bind_group,
-        );
-
-        drop(render_pass);
+        self.scene.draw(&mut render_encoder, &view);
 
         self.egui_platform.begin_frame();
         let fps = self.fps.tick();
@@ -406,14 +287,98 @@ impl State {
                 ui.with_layout(Layout::right_to_left(Align::Min), |ui| ui.label(fps_text));
             });
 
+        let save = egui::Window::new("Scenario")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| self.scenarios.ui(ui))
+            .and_then(|x| x.inner)
+            .unwrap_or(false);
+        if save {
+            let scenario = self.current_scenario();
+            self.scenarios.save(&scenario);
+        }
+
+        egui::Window::new("Simulation")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| {
+                self.scene.boids.flock.simulation.ui(ui)
+            });
+
+        egui::Window::new("Lighting").default_open(false).show(
+            &self.egui_platform.context(),
+            |ui| {
+                self.scene.skybox.sky.ui(ui);
+                ui.separator();
+                self.scene.lighting.ui(ui)
+            },
+        );
+
+        egui::Window::new("Recording").default_open(false).show(
+            &self.egui_platform.context(),
+            |ui| {
+                self.recordings.ui(
+                    ui,
+                    &mut self.scene.boids.flock.recorder,
+                    self.scene.boids.flock.instances.len(),
+                )
+            },
+        );
+
+        egui::Window::new("Replay")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| {
+                self.replays.ui(ui, &mut self.scene.boids.flock.replay)
+            });
+
+        egui::Window::new("Spawn")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| {
+                self.spawns.ui(ui, &mut self.scene.boids.flock)
+            });
+
+        egui::Window::new("Snapshot")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| {
+                self.snapshots.ui(ui, &mut self.scene.boids.flock)
+            });
+
+        egui::Window::new("Figure")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| {
+                let scene = &mut self.scene;
+                let boids = &mut scene.boids;
+                self.figures
+                    .ui(ui, &boids.flock, &scene.camera, &mut boids.tinting)
+            });
+
+        egui::Window::new("Order parameters")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| {
+                self.scene.boids.flock.order.ui(ui)
+            });
+
+        egui::Window::new("Tint")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| {
+                let boids = &mut self.scene.boids;
+                let flock = &boids.flock;
+                boids
+                    .tinting
+                    .ui(ui, flock.instances.len(), &flock.simulation)
+            });
+
+        egui::Window::new("Schools")
+            .default_open(false)
+            .show(&self.egui_platform.context(), |ui| {
+                self.scene.boids.flock.schools.ui(ui)
+            });
+
         TopBottomPanel::bottom("bottom-bar").frame(bottom_bar).show(
             &self.egui_platform.context(),
             |ui| {
-                let mut x = 0f32;
-                let slider = Slider::new(&mut x, 0.0..=100.0);
                 ui.horizontal(|ui| {
-                    ui.label("abc");
-                    ui.add(slider);
+                    Preset::ui(ui, &mut self.scene.boids.flock.simulation);
+                    ui.separator();
+                    self.scene.boids.flock.timeline_ui(ui)
                 })
             },
         );
@@ -464,6 +429,18 @@ impl State {
         Ok(())
     }
 
+    /// Simulation steps taken since the flock was spawned
+    pub(crate) fn steps(&self) -> u64 {
+        self.scene.boids.flock.steps
+    }
+
+    /// Finishes any running recording before the tank closes
+    pub(crate) fn shutdown(&mut self) {
+        if let Err(e) = self.scene.boids.flock.stop_recording() {
+            log::error!("Recording not finished: {:#}", e);
+        }
+    }
+
     pub fn window(&self) -> &Window {
         &self.window
     }
@@ -478,7 +455,55 @@ impl State {
 async fn configure_surface(
     window: &Window,
     size: PhysicalSize<u32>,
-) -> (Device, Queue, SurfaceConfiguration, Surface, TextureFormat) {
+    options: &Options,
+) -> anyhow::Result<(Device, Queue, SurfaceConfiguration, Surface, TextureFormat)> {
+    let instance = create_instance();
+    let surface = unsafe { instance.create_surface(window) }?;
+    trace!("Surface successfully created");
+
+    let (adapter, device, queue) = request_device(&instance, Some(&surface), options).await?;
+
+    trace!("Configuring surface");
+    let surface_caps = surface.get_capabilities(&adapter);
+    // Shader code in this tutorial assumes an sRGB surface texture. Using a different
+    // one will result all the colors coming out darker. If you want to support non
+    // sRGB surfaces, you'll need to account for that when drawing to the frame.
+    let surface_format = surface_caps
+        .formats
+        .iter()
+        .copied()
+        .find(|f| f.is_srgb())
+        .unwrap_or(surface_caps.formats[0]);
+
+    let present_mode = match options.present_mode {
+        // wgpu resolves the automatic modes to one that is supported
+        Some(mode @ (PresentMode::AutoVsync | PresentMode::AutoNoVsync)) => mode,
+        Some(mode) if surface_caps.present_modes.contains(&mode) => mode,
+        Some(mode) => {
+            warn!(
+                "Present mode {:?} not supported, using {:?}",
+                mode, surface_caps.present_modes[0]
+            );
+            surface_caps.present_modes[0]
+        }
+        None => surface_caps.present_modes[0],
+    };
+
+    let config = SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: surface_format,
+        width: size.width,
+        height: size.height,
+        present_mode,
+        alpha_mode: surface_caps.alpha_modes[0],
+        view_formats: vec![],
+    };
+    surface.configure(&device, &config);
+
+    Ok((device, queue, config, surface, surface_format))
+}
+
+pub(crate) fn create_instance() -> wgpu::Instance {
     // The instance is a handle to our GPU
     // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
     let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -486,122 +511,91 @@ async fn configure_surface(
         dx12_shader_compiler: Default::default(),
     });
     trace!("WGPU instance successfully created");
+    instance
+}
 
-    let surface = unsafe { instance.create_surface(window) }.unwrap();
-    trace!("Surface successfully created");
-
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::default(),
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        })
-        .await
-        .unwrap();
-
+/// Opens a device on the adapter chosen by `options`. With a `surface`,
+/// only adapters that can draw to it are considered.
+pub(crate) async fn request_device(
+    instance: &wgpu::Instance,
+    surface: Option<&Surface>,
+    options: &Options,
+) -> anyhow::Result<(wgpu::Adapter, Device, Queue)> {
     trace!("Searching for graphics adapter...");
     let adapter_timer = Instant::now();
+    let adapter = match &options.adapter {
+        Some(name) => find_adapter(instance, surface, name)?,
+        None => instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: surface,
+                force_fallback_adapter: options.fallback_adapter,
+            })
+            .await
+            .context("No suitable graphics adapter found")?,
+    };
+    debug!("Using adapter {:?}", adapter.get_info());
+
     let (device, queue) = adapter
         .request_device(
             &wgpu::DeviceDescriptor {
                 features: wgpu::Features::empty(),
                 // WebGL doesn't support all of wgpu's features, so if
                 // we're building for the web we'll have to disable some.
+                // Elsewhere, ask for no more than software and GL
+                // adapters offer.
                 limits: if cfg!(target_arch = "wasm32") {
                     wgpu::Limits::downlevel_webgl2_defaults()
                 } else {
-                    wgpu::Limits::default()
+                    wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits())
                 },
                 label: None,
             },
             None, // Trace path
         )
-        .await
-        .unwrap();
+        .await?;
     trace!(
         "Found graphics adapter (took {:.2?})",
         adapter_timer.elapsed()
     );
+    Ok((adapter, device, queue))
+}
 
-    trace!("Configuring surface");
-    let surface_caps = surface.get_capabilities(&adapter);
-    // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-    // one will result all the colors coming out darker. If you want to support non
-    // sRGB surfaces, you'll need to account for that when drawing to the frame.
-    let surface_format = surface_caps
-        .formats
+/// The first adapter whose name contains `name`, among those able to draw
+/// to `surface` if given
+#[cfg(not(target_arch = "wasm32"))]
+fn find_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&Surface>,
+    name: &str,
+) -> anyhow::Result<wgpu::Adapter> {
+    let adapters = instance
+        .enumerate_adapters(wgpu::Backends::all())
+        .filter(|x| surface.map_or(true, |surface| x.is_surface_supported(surface)))
+        .collect::<Vec<_>>();
+    let names = adapters
         .iter()
-        .copied()
-        .find(|f| f.is_srgb())
-        .unwrap_or(surface_caps.formats[0]);
-
-    let config = SurfaceConfiguration {
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format: surface_format,
-        width: size.width,
-        height: size.height,
-        present_mode: surface_caps.present_modes[0],
-        alpha_mode: surface_caps.alpha_modes[0],
-        view_formats: vec![],
-    };
-    surface.configure(&device, &config);
-
-    (device, queue, config, surface, surface_format)
+        .map(|x| format!("{} ({:?})", x.get_info().name, x.get_info().backend))
+        .collect::<Vec<_>>();
+    let wanted = name.to_lowercase();
+    adapters
+        .into_iter()
+        .find(|x| x.get_info().name.to_lowercase().contains(&wanted))
+        .with_context(|| {
+            format!(
+                "No adapter matching '{}', found: {}",
+                name,
+                names.join(", ")
+            )
+        })
 }
-fn create_render_pipeline(
-    device: &wgpu::Device,
-    layout: &wgpu::PipelineLayout,
-    color_format: wgpu::TextureFormat,
-    depth_format: Option<wgpu::TextureFormat>,
-    vertex_layouts: &[wgpu::VertexBufferLayout],
-    shader: wgpu::ShaderModuleDescriptor,
-) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render Pipeline"),
-        layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
-            buffers: vertex_layouts,
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
-            targets: &[Some(wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-            polygon_mode: wgpu::PolygonMode::Fill,
-            // Requires Features::DEPTH_CLIP_CONTROL
-            unclipped_depth: false,
-            // Requires Features::CONSERVATIVE_RASTERIZATION
-            conservative: false,
-        },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
-        multisample: wgpu::MultisampleState {
-            count: MSAA_SAMPLE_COUNT,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        },
-        multiview: None,
-    })
+
+/// Browsers only ever offer one adapter
+#[cfg(target_arch = "wasm32")]
+fn find_adapter(
+    _instance: &wgpu::Instance,
+    _surface: Option<&Surface>,
+    _name: &str,
+) -> anyhow::Result<wgpu::Adapter> {
+    anyhow::bail!("Choosing an adapter isn't supported on the web")
 }
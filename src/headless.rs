@@ -0,0 +1,337 @@
+use crate::analysis::{OrderParameters, Phase, Schools};
+use crate::flock::Flock;
+use crate::recording::{write_order_row, Recorder, RecordingFormat, ORDER_CSV_HEADER};
+use crate::scenario::Scenario;
+use crate::simulation::TIME_STEP;
+use crate::svg::Figure;
+use crate::tint::Tinting;
+use crate::Options;
+use anyhow::Context;
+use instant::Instant;
+use log::info;
+use serde::Serialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// What a run without a window writes out besides an optional recording
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Steps at the start left out of the statistics while the flock settles
+    pub warmup: u64,
+    /// Writes the summary statistics here as JSON instead of printing them
+    pub summary: Option<PathBuf>,
+    /// Writes the order parameters here as CSV, in the same format as the
+    /// `.order.csv` file of a recording, or on stdout for `-`, which moves
+    /// a printed summary to stderr
+    pub series: Option<PathBuf>,
+    /// Steps between rows of the series, every step when 0 or 1
+    pub interval: u64,
+    /// Draws the flock as it is at the end into this SVG file
+    pub svg: Option<PathBuf>,
+    /// How the SVG is drawn, through the scenario's camera or along an axis
+    pub figure: Figure,
+}
+
+/// Runs the simulation as fast as it goes without opening a window or
+/// touching the GPU, until the limit in `options` is reached. Frame limits
+/// count simulation steps.
+pub fn run_headless(options: Options, report: Report) -> anyhow::Result<()> {
+    crate::init_logging();
+
+    let scenario = Scenario::open(options.scenario.as_deref(), &options.overrides)?;
+    let steps = [options.limit.duration_steps(), options.limit.frames]
+        .into_iter()
+        .flatten()
+        .min()
+        .context("Running without a window needs a duration or frame limit")?;
+
+    let mut flock = Flock::new();
+    // Only the SVG's trails look back in time, so don't keep more than they
+    // need
+    flock.history.duration = match report.svg {
+        Some(_) => report.figure.trail,
+        None => 0.0,
+    };
+    scenario.apply(&mut flock, None)?;
+    if let Some(path) = &options.record {
+        let format = RecordingFormat::from_path(path);
+        flock.recorder = Some(Recorder::create(path, format, flock.instances.len())?);
+    }
+    let series_to_stdout = report.series.as_deref() == Some(Path::new("-"));
+    let mut series = match &report.series {
+        Some(path) => {
+            let out: Box<dyn Write> = if series_to_stdout {
+                Box::new(io::stdout().lock())
+            } else {
+                Box::new(
+                    File::create(path).with_context(|| format!("Creating {}", path.display()))?,
+                )
+            };
+            let mut out = BufWriter::new(out);
+            writeln!(out, "{}", ORDER_CSV_HEADER)?;
+            Some(out)
+        }
+        None => None,
+    };
+
+    let timer = Instant::now();
+    let mut statistics = Statistics::default();
+    for _ in 0..steps {
+        flock.step();
+        if let Some(e) = flock.recorder_error.take() {
+            return Err(e.context("Recording stopped"));
+        }
+        if let Some(out) = &mut series {
+            if flock.steps % report.interval.max(1) == 0 {
+                let result = write_order_row(out, flock.steps, &flock.order, &flock.schools);
+                if closed_pipe(result)? {
+                    series = None;
+                }
+            }
+        }
+        if flock.steps > report.warmup {
+            statistics.add(&flock.order, &flock.schools);
+        }
+    }
+    flock.stop_recording()?;
+    if let Some(mut out) = series {
+        closed_pipe(out.flush())?;
+    }
+    let elapsed = timer.elapsed();
+    info!("Ran {} steps in {:.2?}", steps, elapsed);
+
+    if let Some(path) = &report.svg {
+        let render = &scenario.render;
+        let camera = scenario
+            .camera
+            .to_camera(render.width as f32 / render.height as f32);
+        report
+            .figure
+            .save(path, &flock, &camera, &mut Tinting::new())?;
+    }
+
+    let summary = Summary {
+        boids: flock.instances.len(),
+        steps: flock.steps,
+        simulated_seconds: flock.steps as f32 * TIME_STEP,
+        wall_seconds: elapsed.as_secs_f32(),
+        sampled_steps: statistics.count,
+        polarization: statistics.polarization.finish(),
+        milling: statistics.milling.finish(),
+        nearest_neighbour: statistics.nearest_neighbour.finish(),
+        extent: statistics.extent.finish(),
+        schools: statistics.schools.finish(),
+        phases: statistics.phases(),
+    };
+    match &report.summary {
+        Some(path) => {
+            let file =
+                File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), &summary)?;
+        }
+        // Keep the series on stdout as plain CSV
+        None if series_to_stdout => eprint!("{}", summary),
+        None => {
+            let mut out = io::stdout().lock();
+            closed_pipe(write!(out, "{}", summary).and_then(|_| out.flush()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `result` failed because whatever was reading the output has
+/// gone away, as with `| head`, which isn't worth failing the run over
+fn closed_pipe(result: io::Result<()>) -> io::Result<bool> {
+    match result {
+        Ok(()) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// Running statistics of the order parameters over the measured steps
+#[derive(Default)]
+struct Statistics {
+    count: u64,
+    polarization: Moments,
+    milling: Moments,
+    nearest_neighbour: Moments,
+    extent: Moments,
+    schools: Moments,
+    /// Steps spent in each phase, in the order of [`PHASES`]
+    phases: [u64; 4],
+}
+
+const PHASES: [Phase; 4] = [
+    Phase::School,
+    Phase::Mill,
+    Phase::Swarm,
+    Phase::Transitional,
+];
+
+impl Statistics {
+    fn add(&mut self, order: &OrderParameters, schools: &Schools) {
+        self.count += 1;
+        self.polarization.add(order.polarization);
+        self.milling.add(order.milling);
+        self.nearest_neighbour.add(order.nearest_neighbour);
+        self.extent.add(order.extent);
+        self.schools.add(schools.clusters.len() as f32);
+        let phase = order.phase();
+        if let Some(i) = PHASES.iter().position(|x| *x == phase) {
+            self.phases[i] += 1;
+        }
+    }
+
+    /// Fraction of the measured steps spent in each phase
+    fn phases(&self) -> PhaseFractions {
+        let fraction = |i: usize| self.phases[i] as f32 / self.count.max(1) as f32;
+        PhaseFractions {
+            school: fraction(0),
+            mill: fraction(1),
+            swarm: fraction(2),
+            transitional: fraction(3),
+        }
+    }
+}
+
+/// Mean and variance by Welford's online algorithm, plus the range
+#[derive(Default)]
+struct Moments {
+    count: u64,
+    mean: f64,
+    /// Sum of squared differences from the mean
+    m2: f64,
+    min: f32,
+    max: f32,
+}
+
+impl Moments {
+    fn add(&mut self, value: f32) {
+        if self.count == 0 {
+            (self.min, self.max) = (value, value);
+        }
+        self.count += 1;
+        let x = value as f64;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn finish(&self) -> Stats {
+        Stats {
+            mean: self.mean as f32,
+            std: (self.m2 / self.count.max(1) as f64).sqrt() as f32,
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Stats {
+    mean: f32,
+    std: f32,
+    min: f32,
+    max: f32,
+}
+
+#[derive(Serialize)]
+struct PhaseFractions {
+    school: f32,
+    mill: f32,
+    swarm: f32,
+    transitional: f32,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    boids: usize,
+    steps: u64,
+    simulated_seconds: f32,
+    wall_seconds: f32,
+    /// Steps after the warm-up that the statistics cover
+    sampled_steps: u64,
+    polarization: Stats,
+    milling: Stats,
+    nearest_neighbour: Stats,
+    extent: Stats,
+    schools: Stats,
+    phases: PhaseFractions,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} boids, {} steps, {:.1} s simulated in {:.1} s",
+            self.boids, self.steps, self.simulated_seconds, self.wall_seconds
+        )?;
+        writeln!(f, "statistics over the last {} steps", self.sampled_steps)?;
+        writeln!(
+            f,
+            "{:<18} {:>8} {:>8} {:>8} {:>8}",
+            "", "mean", "std", "min", "max"
+        )?;
+        let rows = [
+            ("polarization", &self.polarization),
+            ("milling", &self.milling),
+            ("nearest neighbour", &self.nearest_neighbour),
+            ("extent", &self.extent),
+            ("schools", &self.schools),
+        ];
+        for (name, stats) in rows {
+            writeln!(
+                f,
+                "{:<18} {:>8.3} {:>8.3} {:>8.3} {:>8.3}",
+                name, stats.mean, stats.std, stats.min, stats.max
+            )?;
+        }
+        let phases = &self.phases;
+        writeln!(
+            f,
+            "phases: school {:.0}%, mill {:.0}%, swarm {:.0}%, transitional {:.0}%",
+            phases.school * 100.0,
+            phases.mill * 100.0,
+            phases.swarm * 100.0,
+            phases.transitional * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moments_match_naive_statistics() {
+        let values = [0.25, 3.0, -1.5, 1000.0, 1000.5, 7.0, 0.0];
+        let mut moments = Moments::default();
+        for value in values {
+            moments.add(value);
+        }
+        let stats = moments.finish();
+
+        let n = values.len() as f64;
+        let mean = values.iter().map(|&x| x as f64).sum::<f64>() / n;
+        let variance = values
+            .iter()
+            .map(|&x| (x as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        assert!((stats.mean as f64 - mean).abs() < 1e-4);
+        assert!((stats.std as f64 - variance.sqrt()).abs() < 1e-4);
+        assert_eq!(stats.min, -1.5);
+        assert_eq!(stats.max, 1000.5);
+    }
+
+    #[test]
+    fn moments_of_nothing() {
+        let stats = Moments::default().finish();
+        assert_eq!((stats.mean, stats.std), (0.0, 0.0));
+    }
+}
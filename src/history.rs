@@ -0,0 +1,184 @@
+use crate::instance::Instance;
+use crate::simulation::TIME_STEP;
+use cgmath::Vector3;
+use egui::{Slider, Ui};
+use std::collections::VecDeque;
+
+/// Most memory the frames may take up. Large flocks get less than
+/// [`History::duration`] to rewind through rather than running out.
+const MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Where every fish was and where it was heading after one simulation step
+pub struct Frame {
+    pub step: u64,
+    pub positions: Vec<Vector3<f32>>,
+    pub velocities: Vec<Vector3<f32>>,
+}
+
+impl Frame {
+    fn new(step: u64, instances: &[Instance]) -> Self {
+        Self {
+            step,
+            positions: instances.iter().map(|x| x.position).collect(),
+            velocities: instances.iter().map(|x| x.velocity).collect(),
+        }
+    }
+
+    /// Number of fish in the frame
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Moves `instances` back to where they were in this frame. Fish keep
+    /// their species, any the frame doesn't know about are left out.
+    pub fn restore(&self, instances: &mut Vec<Instance>) {
+        instances.truncate(self.len());
+        for (i, (&position, &velocity)) in self.positions.iter().zip(&self.velocities).enumerate() {
+            match instances.get_mut(i) {
+                Some(instance) => {
+                    instance.position = position;
+                    instance.velocity = velocity;
+                    instance.face_velocity();
+                }
+                None => instances.push(Instance::new(position, velocity, 0)),
+            }
+        }
+    }
+}
+
+/// Ring buffer of the most recent simulation steps, for scrubbing back
+/// through what just happened in the tank.
+pub struct History {
+    /// Seconds of simulated time to keep, nothing is kept when 0
+    pub duration: f32,
+    frames: VecDeque<Frame>,
+    /// Frame being shown while scrubbing, `None` while live
+    cursor: Option<usize>,
+}
+
+impl History {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            frames: VecDeque::new(),
+            cursor: None,
+        }
+    }
+
+    /// Frames to keep of a flock of `count` fish
+    fn capacity(&self, count: usize) -> usize {
+        let frame_bytes = count.max(1) * 2 * std::mem::size_of::<Vector3<f32>>();
+        let wanted = (self.duration.max(0.0) / TIME_STEP).ceil() as usize;
+        wanted.min(MAX_BYTES / frame_bytes)
+    }
+
+    pub fn record(&mut self, step: u64, instances: &[Instance]) {
+        let capacity = self.capacity(instances.len());
+        while !self.frames.is_empty() && self.frames.len() >= capacity {
+            self.frames.pop_front();
+        }
+        if capacity > 0 {
+            self.frames.push_back(Frame::new(step, instances));
+        }
+    }
+
+    pub fn is_scrubbing(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Moves the cursor to `index` and returns the frame there
+    pub fn scrub(&mut self, index: usize) -> Option<&Frame> {
+        let index = index.min(self.frames.len().checked_sub(1)?);
+        self.cursor = Some(index);
+        self.frames.get(index)
+    }
+
+    /// Goes live again from the scrubbed frame, forgetting everything after it
+    pub fn resume(&mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            self.frames.truncate(cursor + 1);
+        }
+    }
+
+    /// Frames covering the last `duration` seconds up to the one shown,
+    /// oldest first
+    pub fn recent(&self, duration: f32) -> impl Iterator<Item = &Frame> {
+        let end = self.cursor.map_or(self.frames.len(), |x| x + 1);
+        let count = (duration / TIME_STEP).ceil() as usize + 1;
+        self.frames.range(end.saturating_sub(count)..end)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.cursor = None;
+    }
+
+    /// Draws the timeline slider, returning the index the user scrubbed to
+    pub(crate) fn ui(&mut self, ui: &mut Ui) -> Option<usize> {
+        let last = self.frames.len().saturating_sub(1);
+        let mut index = self.cursor.unwrap_or(last);
+        let label = format!("-{:.1}s", (last - index) as f32 * TIME_STEP);
+        ui.spacing_mut().slider_width = (ui.available_width() - 60.0).max(100.0);
+
+        let response = ui.add_enabled(
+            last > 0,
+            Slider::new(&mut index, 0..=last)
+                .show_value(false)
+                .text(label),
+        );
+        response.changed().then_some(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flock(count: usize) -> Vec<Instance> {
+        (0..count)
+            .map(|i| Instance::new(Vector3::new(i as f32, 0.0, 0.0), Vector3::unit_x(), 0))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_last_duration() {
+        let mut history = History::new(10.0 * TIME_STEP);
+        let instances = flock(5);
+        for step in 0..25 {
+            history.record(step, &instances);
+        }
+        let steps = history.recent(1000.0).map(|x| x.step).collect::<Vec<_>>();
+        assert_eq!(steps, (15..25).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn large_flocks_keep_less() {
+        let history = History::new(30.0);
+        assert_eq!(history.capacity(50), 1800);
+        let frames = history.capacity(10_000);
+        assert!(frames < 1800);
+        assert!(frames * 10_000 * 24 <= MAX_BYTES);
+    }
+
+    #[test]
+    fn nothing_is_kept_without_a_duration() {
+        let mut history = History::new(0.0);
+        history.record(0, &flock(5));
+        assert_eq!(history.recent(1000.0).count(), 0);
+    }
+
+    #[test]
+    fn restores_positions_and_velocities() {
+        let mut history = History::new(1.0);
+        let mut instances = flock(3);
+        history.record(0, &instances);
+        for instance in &mut instances {
+            instance.position.y = 5.0;
+            instance.velocity = Vector3::unit_z();
+        }
+
+        history.scrub(0).unwrap().restore(&mut instances);
+        assert!(instances.iter().all(|x| x.position.y == 0.0));
+        assert!(instances.iter().all(|x| x.velocity == Vector3::unit_x()));
+    }
+}
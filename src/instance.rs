@@ -1,4 +1,4 @@
-use cgmath::Matrix4;
+use cgmath::{InnerSpace, Matrix3, Matrix4, One, Quaternion, Vector3};
 use std::mem::size_of;
 
 #[repr(C)]
@@ -27,12 +27,44 @@ impl InstanceRaw {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct Instance {
     pub(crate) position: cgmath::Vector3<f32>,
+    pub(crate) velocity: cgmath::Vector3<f32>,
     pub(crate) rotation: cgmath::Quaternion<f32>,
+    /// Index into the simulation's species list
+    pub(crate) species: usize,
 }
 
 impl Instance {
+    pub(crate) fn new(position: Vector3<f32>, velocity: Vector3<f32>, species: usize) -> Self {
+        let mut instance = Self {
+            position,
+            velocity,
+            rotation: Quaternion::one(),
+            species,
+        };
+        instance.face_velocity();
+        instance
+    }
+
+    /// Turns the fish so its nose (the model's +x axis) points along its
+    /// velocity, keeping its back towards +y.
+    pub(crate) fn face_velocity(&mut self) {
+        if self.velocity.magnitude2() == 0.0 {
+            return;
+        }
+        let forward = self.velocity.normalize();
+        let mut side = forward.cross(Vector3::unit_y());
+        if side.magnitude2() < 1e-6 {
+            side = forward.cross(Vector3::unit_z());
+        }
+        let side = side.normalize();
+        let up = side.cross(forward);
+
+        self.rotation = Quaternion::from(Matrix3::from_cols(forward, up, side));
+    }
+
     pub(crate) fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
             model: (Matrix4::from_translation(self.position) * Matrix4::from(self.rotation)).into(),
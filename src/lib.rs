@@ -1,56 +1,147 @@
+mod analysis;
 /// Just some utility to making bind groups easier
 mod bind_group;
 mod boids;
 mod camera;
 mod camera_controller;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
+mod flock;
 mod graphics;
+#[cfg(not(target_arch = "wasm32"))]
+mod headless;
+mod history;
 mod instance;
+mod light;
 mod mipmaps;
 mod model;
+#[cfg(not(target_arch = "wasm32"))]
+mod offscreen;
+mod preset;
+mod recording;
+mod replay;
 mod resources;
+mod scenario;
+mod scene;
+mod shadow;
+mod simulation;
+mod skybox;
+mod snapshot;
+mod spatial;
+mod spawn;
+mod svg;
+#[cfg(not(target_arch = "wasm32"))]
+mod terminal;
 // mod octree;
 mod texture;
+mod tint;
+#[cfg(not(target_arch = "wasm32"))]
+mod y4m;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::cli::{FlockArgs, ReportArgs};
 use crate::graphics::State;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::headless::{run_headless, Report};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::offscreen::{export_frames, Export, Output};
+pub use crate::preset::Preset;
+pub use crate::scenario::Overrides;
+use crate::scenario::ScenarioPanel;
+pub use crate::simulation::SimulationModel;
+use crate::simulation::TIME_STEP;
+pub use crate::skybox::Sky;
+pub use crate::svg::{Figure, Projection};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::terminal::{run_terminal, Glyphs, Terminal};
+pub use crate::tint::TintMode;
 use instant::Instant;
 use log::{debug, trace, warn};
+use std::path::PathBuf;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 use wgpu::SurfaceError;
 use winit::dpi::LogicalSize;
-use winit::event::Event::UserEvent;
 use winit::event::{Event, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoopBuilder};
-use winit::platform::windows::EventLoopBuilderExtWindows;
 use winit::window::{WindowBuilder, WindowId};
 
+/// Default window size, see [`scenario::RenderSettings`]
 const SIZE_X: u32 = 600;
 const SIZE_Y: u32 = 600;
 
+/// How to start the tank, see [`run_with`]
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// TOML scenario loaded at startup and reloaded whenever it changes
+    pub scenario: Option<PathBuf>,
+    pub overrides: Overrides,
+    /// How frames are presented, the first mode the surface supports when
+    /// not given
+    pub present_mode: Option<wgpu::PresentMode>,
+    /// Picks the first graphics adapter whose name contains this, ignoring
+    /// case
+    pub adapter: Option<String>,
+    /// Asks for wgpu's software fallback adapter, for machines without a
+    /// usable GPU
+    pub fallback_adapter: bool,
+    /// Records every step from the start, in the binary format for
+    /// `.boids` files and as CSV otherwise
+    pub record: Option<PathBuf>,
+    pub limit: Limit,
+}
+
+/// When a run stops by itself. Runs without a limit go on until the window
+/// is closed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limit {
+    /// Seconds of simulated time
+    pub duration: Option<f32>,
+    /// Frames drawn, or simulation steps taken when there is no window
+    pub frames: Option<u64>,
+}
+
+impl Limit {
+    /// Steps needed to cover the duration, if any
+    fn duration_steps(&self) -> Option<u64> {
+        self.duration
+            .map(|duration| (duration / TIME_STEP).ceil() as u64)
+    }
+
+    /// Whether a run that took `steps` simulation steps and drew `frames`
+    /// frames is over
+    fn reached(&self, steps: u64, frames: u64) -> bool {
+        self.duration_steps().is_some_and(|x| steps >= x)
+            || self.frames.is_some_and(|x| frames >= x)
+    }
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
-    // Initiate loggers
-    cfg_if::cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-            console_log::init_with_level(log::Level::Info).expect("Couldn't initialize logger");
-            trace!("console_log is active");
-        } else {
-            env_logger::init();
-            trace!("env_logger is active");
-        }
+    if let Err(e) = run_with(Options::default()).await {
+        log::error!("{:#}", e);
     }
+}
+
+/// Like [`run`], but with a scenario file and settings overriding it.
+/// Only returns early, with the error that kept the tank from starting.
+pub async fn run_with(options: Options) -> anyhow::Result<()> {
+    init_logging();
 
     debug!("--- System info ---");
     debug!("OS: {}", std::env::consts::OS);
     debug!("Architecture: {}", std::env::consts::ARCH);
 
+    let (scenario, scenarios) =
+        ScenarioPanel::open(options.scenario.clone(), options.overrides.clone());
+    let (width, height) = (scenario.render.width, scenario.render.height);
+
     trace!("Starting window creation");
     let now = Instant::now();
-    let event_loop = EventLoopBuilder::<GEvent>::with_user_event().build();
+    let event_loop = EventLoopBuilder::new().build();
     let window = WindowBuilder::new()
         .with_title("Boids")
-        .with_inner_size(LogicalSize::new(SIZE_X, SIZE_Y))
+        .with_inner_size(LogicalSize::new(width, height))
         .build(&event_loop)
         .unwrap();
     debug!("Window creation finished in {:.2?}", now.elapsed());
@@ -60,8 +151,8 @@ pub async fn run() {
         // winit prevents sizing with CSS, so we have to set
         // the size manually when on web.
         use winit::dpi::PhysicalSize;
-        window.set_inner_size(PhysicalSize::new(SIZE_X, SIZE_Y));
-        trace!("Set window inner size to {}, {}", SIZE_X, SIZE_Y);
+        window.set_inner_size(PhysicalSize::new(width, height));
+        trace!("Set window inner size to {}, {}", width, height);
 
         use winit::platform::web::WindowExtWebSys;
         web_sys::window()
@@ -75,8 +166,9 @@ pub async fn run() {
             .expect("Couldn't append canvas to document body.");
     }
 
-    let mut state = State::new(window).await;
+    let mut state = State::new(window, scenario, scenarios, &options).await?;
     let mut last_frame = Instant::now();
+    let mut frames = 0;
 
     trace!("Starting window event loop");
     event_loop.run(move |event, _, control_flow| {
@@ -93,7 +185,7 @@ pub async fn run() {
 
                 state.update(delta_s);
                 match state.render() {
-                    Ok(_) => {}
+                    Ok(_) => frames += 1,
                     // Reconfigure the surface if lost
                     Err(SurfaceError::Lost) => state.resize(*state.size()),
                     // The system is out of memory, we should probably quit
@@ -105,14 +197,33 @@ pub async fn run() {
                     }
                 }
             }
-            Event::MainEventsCleared | UserEvent(GEvent::RequestRedraw) => {
-                state.window().request_redraw();
+            Event::MainEventsCleared => {
+                if options.limit.reached(state.steps(), frames) {
+                    debug!("Stopping after {} frames", frames);
+                    control_flow.set_exit();
+                } else {
+                    state.window().request_redraw();
+                }
             }
+            Event::LoopDestroyed => state.shutdown(),
             _ => (),
         }
     });
 }
 
+fn init_logging() {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+            console_log::init_with_level(log::Level::Info).expect("Couldn't initialize logger");
+            trace!("console_log is active");
+        } else {
+            env_logger::init();
+            trace!("env_logger is active");
+        }
+    }
+}
+
 fn handle_window_event(
     event: WindowEvent,
     window_id: WindowId,
@@ -141,18 +252,3 @@ fn handle_window_event(
         _ => {}
     }
 }
-#[derive(Debug)]
-enum GEvent {
-    RequestRedraw,
-}
-struct ExampleRepaintSignal(std::sync::Mutex<winit::event_loop::EventLoopProxy<GEvent>>);
-
-impl epi::backend::RepaintSignal for ExampleRepaintSignal {
-    fn request_repaint(&self) {
-        self.0
-            .lock()
-            .unwrap()
-            .send_event(GEvent::RequestRedraw)
-            .ok();
-    }
-}
@@ -0,0 +1,142 @@
+use egui::{DragValue, Slider, Ui};
+use serde::{Deserialize, Serialize};
+
+/// Most directional lights the shaders take
+pub const MAX_LIGHTS: usize = 4;
+
+/// Light shining the same way everywhere in the tank, like the sun
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DirectionalLight {
+    /// Direction the light travels in, from the light into the tank
+    pub direction: [f32; 3],
+    /// Colour times brightness, components may go above 1
+    pub color: [f32; 3],
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: [-0.3, -1.0, -0.4],
+            color: [0.9, 0.9, 0.85],
+        }
+    }
+}
+
+/// Blinn-Phong lighting of the fish and the aquarium
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Lighting {
+    /// Light reaching every surface from everywhere
+    pub ambient: [f32; 3],
+    /// At most [`MAX_LIGHTS`], the first being the main light
+    pub lights: Vec<DirectionalLight>,
+    /// Strength of the highlights
+    pub specular: f32,
+    /// Blinn-Phong exponent, higher for smaller and sharper highlights
+    pub shininess: f32,
+    /// Whether the fish cast shadows from the main light onto the tank
+    pub shadows: bool,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            ambient: [0.25, 0.3, 0.35],
+            lights: vec![
+                DirectionalLight::default(),
+                // Faint blue fill from below, as if scattered by the water
+                DirectionalLight {
+                    direction: [0.4, 0.6, 0.5],
+                    color: [0.1, 0.15, 0.25],
+                },
+            ],
+            specular: 0.4,
+            shininess: 32.0,
+            shadows: true,
+        }
+    }
+}
+
+impl Lighting {
+    pub(crate) fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Ambient");
+            ui.color_edit_button_rgb(&mut self.ambient);
+        });
+        ui.add(Slider::new(&mut self.specular, 0.0..=2.0).text("Specular"));
+        ui.add(
+            Slider::new(&mut self.shininess, 1.0..=256.0)
+                .logarithmic(true)
+                .text("Shininess"),
+        );
+
+        ui.checkbox(&mut self.shadows, "Shadows from light 1");
+
+        ui.separator();
+        let mut removed = None;
+        for (i, light) in self.lights.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("Light {}", i + 1));
+                ui.color_edit_button_rgb(&mut light.color);
+                for (value, axis) in light.direction.iter_mut().zip(["x", "y", "z"]) {
+                    ui.add(
+                        DragValue::new(value)
+                            .speed(0.01)
+                            .clamp_range(-1.0..=1.0)
+                            .prefix(format!("{}: ", axis)),
+                    );
+                }
+                if ui.small_button("✖").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            self.lights.remove(i);
+        }
+        if self.lights.len() < MAX_LIGHTS && ui.button("Add light").clicked() {
+            self.lights.push(DirectionalLight::default());
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    direction: [f32; 3],
+    _padding: f32,
+    color: [f32; 3],
+    _padding2: f32,
+}
+
+/// [`Lighting`] laid out as the `Lighting` struct in the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightingUniform {
+    lights: [LightRaw; MAX_LIGHTS],
+    ambient: [f32; 3],
+    count: u32,
+    specular: f32,
+    shininess: f32,
+    _padding: [f32; 2],
+}
+
+impl LightingUniform {
+    pub(crate) fn new(lighting: &Lighting) -> Self {
+        let mut lights = [LightRaw::default(); MAX_LIGHTS];
+        let count = lighting.lights.len().min(MAX_LIGHTS);
+        for (raw, light) in lights.iter_mut().zip(&lighting.lights) {
+            raw.direction = light.direction;
+            raw.color = light.color;
+        }
+        Self {
+            lights,
+            ambient: lighting.ambient,
+            count: count as u32,
+            specular: lighting.specular,
+            shininess: lighting.shininess,
+            _padding: [0.0; 2],
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use crate::graphics::{create_instance, request_device};
+use crate::recording::{Recorder, RecordingFormat};
+use crate::scenario::Scenario;
+use crate::scene::Scene;
+use crate::simulation::TIME_STEP;
+use crate::y4m::Y4mWriter;
+use crate::Options;
+use anyhow::Context;
+use image::RgbaImage;
+use instant::Instant;
+use log::{debug, info};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use wgpu::{Device, Queue};
+
+/// Seed used when exporting a scenario that doesn't give one, so the same
+/// command always renders the same frames
+const DEFAULT_SEED: u64 = 0;
+
+/// Colour format frames are rendered in, read back as 8-bit sRGB
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Draws the scene into a texture instead of a window and reads every frame
+/// back to the CPU
+pub(crate) struct Offscreen {
+    device: Device,
+    queue: Queue,
+    pub(crate) scene: Scene,
+    target: wgpu::Texture,
+    view: wgpu::TextureView,
+    readback: wgpu::Buffer,
+    size: (u32, u32),
+    /// Row length in the readback buffer, padded to what copies require
+    padded_row: u32,
+}
+
+impl Offscreen {
+    /// Opens a device without a window and sets up a scene of the size,
+    /// camera and multisampling in `scenario`
+    pub(crate) async fn new(options: &Options, scenario: &Scenario) -> anyhow::Result<Self> {
+        let instance = create_instance();
+        let (_, device, queue) = request_device(&instance, None, options).await?;
+
+        let size = (scenario.render.width, scenario.render.height);
+        let max_size = device.limits().max_texture_dimension_2d;
+        anyhow::ensure!(
+            size.0 <= max_size && size.1 <= max_size,
+            "Frames of {}x{} are larger than the {} pixels the graphics adapter allows",
+            size.0,
+            size.1,
+            max_size
+        );
+        let scene = Scene::new(&device, &queue, FORMAT, size, scenario).await?;
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_target"),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = (size.0 * 4 + align - 1) / align * align;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen_readback"),
+            size: padded_row as wgpu::BufferAddress * size.1 as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            device,
+            queue,
+            scene,
+            target,
+            view,
+            readback,
+            size,
+            padded_row,
+        })
+    }
+
+    /// Uploads the flock and camera as they are now, draws them and waits
+    /// for the finished frame
+    pub(crate) fn render(&mut self) -> anyhow::Result<RgbaImage> {
+        self.scene.boids.upload(&self.device, &self.queue);
+        self.scene.write_camera(&self.queue);
+        self.scene.write_lighting(&self.queue);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("offscreen_encoder"),
+            });
+        self.scene.draw(&mut encoder, &self.view);
+        let (width, height) = self.size;
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.target,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit([encoder.finish()]);
+
+        let slice = self.readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .context("Reading back the frame")?
+            .context("Reading back the frame")?;
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in slice.get_mapped_range().chunks(self.padded_row as usize) {
+            pixels.extend_from_slice(&row[..(width * 4) as usize]);
+        }
+        self.readback.unmap();
+        RgbaImage::from_raw(width, height, pixels).context("Frame has the wrong size")
+    }
+}
+
+/// Where and how often frames are written by [`export_frames`]
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub output: Output,
+    /// Frames per second of simulated time, which has to divide the 60
+    /// simulation steps per second evenly
+    pub fps: f32,
+}
+
+impl Export {
+    /// Simulation steps between frames. Frame rates that would put frames
+    /// between steps are rejected, as rounding them to the nearest step
+    /// makes the motion judder.
+    fn steps_per_frame(&self) -> anyhow::Result<u64> {
+        anyhow::ensure!(
+            self.fps.is_finite() && self.fps > 0.0,
+            "The frame rate must be positive"
+        );
+        let steps = 1.0 / (self.fps as f64 * TIME_STEP as f64);
+        let rounded = steps.round();
+        anyhow::ensure!(
+            rounded >= 1.0 && (steps - rounded).abs() < 1e-4,
+            "The frame rate must divide {} steps per second evenly, like 60, 30, 20 or 15, not {}",
+            (1.0 / TIME_STEP).round(),
+            self.fps
+        );
+        Ok(rounded as u64)
+    }
+}
+
+/// What [`export_frames`] writes the frames to
+#[derive(Debug, Clone)]
+pub enum Output {
+    /// Numbered PNGs in this directory, created if missing
+    Png(PathBuf),
+    /// A single YUV4MPEG2 video in this file, or on stdout for `-`, to be
+    /// piped into an encoder
+    Y4m(PathBuf),
+}
+
+/// Renders the tank without a window at a fixed simulated frame rate and
+/// writes every frame out, until the limit in `options` is reached. The
+/// frames only depend on the scenario and seed, not on how fast they are
+/// rendered. Scenarios without a seed use the same fixed one every time.
+pub async fn export_frames(options: Options, export: Export) -> anyhow::Result<()> {
+    crate::init_logging();
+
+    let steps_per_frame = export.steps_per_frame()?;
+    let mut scenario = Scenario::open(options.scenario.as_deref(), &options.overrides)?;
+    let seed = *scenario.seed.get_or_insert(DEFAULT_SEED);
+    info!("Exporting with seed {}", seed);
+    let frames = match (options.limit.frames, options.limit.duration) {
+        (Some(frames), _) => frames,
+        (None, Some(duration)) => (duration * export.fps).ceil() as u64,
+        (None, None) => anyhow::bail!("Exporting frames needs a duration or frame limit"),
+    };
+
+    let mut offscreen = Offscreen::new(&options, &scenario).await?;
+    let flock = &mut offscreen.scene.boids.flock;
+    scenario.apply(flock, None)?;
+    if let Some(path) = &options.record {
+        let format = RecordingFormat::from_path(path);
+        flock.recorder = Some(Recorder::create(path, format, flock.instances.len())?);
+    }
+
+    let mut sink = Sink::open(&export, offscreen.size)?;
+    let timer = Instant::now();
+    for frame in 0..frames {
+        let steps = frame * steps_per_frame;
+        let flock = &mut offscreen.scene.boids.flock;
+        while flock.steps < steps {
+            flock.step();
+            if let Some(e) = flock.recorder_error.take() {
+                return Err(e.context("Recording stopped"));
+            }
+        }
+
+        let image = offscreen.render()?;
+        sink.write(frame, &image)?;
+    }
+    sink.finish()?;
+    offscreen.scene.boids.flock.stop_recording()?;
+    info!("Wrote {} frames in {:.2?}", frames, timer.elapsed());
+    Ok(())
+}
+
+enum Sink {
+    Png(PathBuf),
+    Y4m {
+        path: PathBuf,
+        writer: Y4mWriter<BufWriter<Box<dyn Write>>>,
+    },
+}
+
+impl Sink {
+    fn open(export: &Export, (width, height): (u32, u32)) -> anyhow::Result<Self> {
+        match &export.output {
+            Output::Png(directory) => {
+                std::fs::create_dir_all(directory)
+                    .with_context(|| format!("Creating {}", directory.display()))?;
+                Ok(Sink::Png(directory.clone()))
+            }
+            Output::Y4m(path) => {
+                let out: Box<dyn Write> = if path.as_os_str() == "-" {
+                    Box::new(std::io::stdout().lock())
+                } else {
+                    Box::new(
+                        File::create(path)
+                            .with_context(|| format!("Creating {}", path.display()))?,
+                    )
+                };
+                let writer = Y4mWriter::new(BufWriter::new(out), width, height, export.fps)
+                    .with_context(|| format!("Writing {}", path.display()))?;
+                Ok(Sink::Y4m {
+                    path: path.clone(),
+                    writer,
+                })
+            }
+        }
+    }
+
+    fn write(&mut self, frame: u64, image: &RgbaImage) -> anyhow::Result<()> {
+        match self {
+            Sink::Png(directory) => {
+                let path = directory.join(format!("frame_{:05}.png", frame));
+                image
+                    .save(&path)
+                    .with_context(|| format!("Writing {}", path.display()))?;
+                debug!("Wrote {}", path.display());
+            }
+            Sink::Y4m { path, writer } => writer
+                .write_frame(image)
+                .with_context(|| format!("Writing {}", path.display()))?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        if let Sink::Y4m { path, writer } = self {
+            writer
+                .finish()
+                .with_context(|| format!("Writing {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(fps: f32) -> Export {
+        Export {
+            output: Output::Png(PathBuf::new()),
+            fps,
+        }
+    }
+
+    #[test]
+    fn frames_land_on_steps() {
+        for (fps, steps) in [(60.0, 1), (30.0, 2), (20.0, 3), (7.5, 8)] {
+            assert_eq!(export(fps).steps_per_frame().unwrap(), steps, "{} fps", fps);
+        }
+        for fps in [24.0, 12.5, 120.0, 0.0, -30.0, f32::NAN, f32::INFINITY] {
+            assert!(export(fps).steps_per_frame().is_err(), "{} fps", fps);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::simulation::{Couzin, Kinematics, Reynolds, Simulation, SimulationModel};
+use egui::{ComboBox, Ui};
+use std::str::FromStr;
+
+/// Named starting points that set the model, its rule weights and radii,
+/// and the kinematic limits all at once. Species and the tank forces are
+/// left as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Strong alignment and cohesion at close range
+    TightSchool,
+    /// Weak alignment, so the fish drift around loosely together
+    LooseShoal,
+    /// Small zone of orientation inside a wide zone of attraction, which
+    /// makes the school circle an empty core (Couzin et al., 2002)
+    MillingTorus,
+    /// Fast, highly aligned flock with long range cohesion
+    Murmuration,
+    /// Separation only, at high speed
+    PanickedScatter,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 5] = [
+        Preset::TightSchool,
+        Preset::LooseShoal,
+        Preset::MillingTorus,
+        Preset::Murmuration,
+        Preset::PanickedScatter,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::TightSchool => "Tight school",
+            Preset::LooseShoal => "Loose shoal",
+            Preset::MillingTorus => "Milling torus",
+            Preset::Murmuration => "Murmuration",
+            Preset::PanickedScatter => "Panicked scatter",
+        }
+    }
+
+    /// Overwrites the model parameters of `simulation` with this preset's
+    pub fn apply(&self, simulation: &mut Simulation) {
+        match self {
+            Preset::TightSchool => {
+                simulation.model = SimulationModel::Reynolds;
+                simulation.reynolds = Reynolds {
+                    separation_radius: 1.2,
+                    separation_weight: 4.0,
+                    alignment_radius: 5.0,
+                    alignment_weight: 2.0,
+                    cohesion_radius: 8.0,
+                    cohesion_weight: 1.0,
+                };
+                simulation.limits = Kinematics {
+                    min_speed: 2.0,
+                    max_speed: 6.0,
+                    max_acceleration: 20.0,
+                    max_turn_rate: 270.0,
+                };
+            }
+            Preset::LooseShoal => {
+                simulation.model = SimulationModel::Reynolds;
+                simulation.reynolds = Reynolds {
+                    separation_radius: 2.5,
+                    separation_weight: 3.0,
+                    alignment_radius: 3.0,
+                    alignment_weight: 0.2,
+                    cohesion_radius: 10.0,
+                    cohesion_weight: 0.3,
+                };
+                simulation.limits = Kinematics {
+                    min_speed: 0.5,
+                    max_speed: 4.0,
+                    max_acceleration: 10.0,
+                    max_turn_rate: 180.0,
+                };
+            }
+            Preset::MillingTorus => {
+                simulation.model = SimulationModel::Couzin;
+                simulation.couzin = Couzin {
+                    repulsion: 1.0,
+                    orientation_width: 0.5,
+                    attraction_width: 12.0,
+                    field_of_view: 270.0,
+                    turning_rate: 60.0,
+                    speed: 3.0,
+                    noise: 0.05,
+                };
+                simulation.limits = Kinematics {
+                    min_speed: 1.0,
+                    max_speed: 8.0,
+                    max_acceleration: 20.0,
+                    max_turn_rate: 360.0,
+                };
+            }
+            Preset::Murmuration => {
+                simulation.model = SimulationModel::Reynolds;
+                simulation.reynolds = Reynolds {
+                    separation_radius: 1.5,
+                    separation_weight: 6.0,
+                    alignment_radius: 6.0,
+                    alignment_weight: 3.0,
+                    cohesion_radius: 12.0,
+                    cohesion_weight: 0.8,
+                };
+                simulation.limits = Kinematics {
+                    min_speed: 5.0,
+                    max_speed: 14.0,
+                    max_acceleration: 60.0,
+                    max_turn_rate: 540.0,
+                };
+            }
+            Preset::PanickedScatter => {
+                simulation.model = SimulationModel::Reynolds;
+                simulation.reynolds = Reynolds {
+                    separation_radius: 6.0,
+                    separation_weight: 10.0,
+                    alignment_radius: 2.0,
+                    alignment_weight: 0.0,
+                    cohesion_radius: 2.0,
+                    cohesion_weight: 0.0,
+                };
+                simulation.limits = Kinematics {
+                    min_speed: 6.0,
+                    max_speed: 12.0,
+                    max_acceleration: 80.0,
+                    max_turn_rate: 720.0,
+                };
+            }
+        }
+    }
+
+    /// Dropdown applying the chosen preset to `simulation`
+    pub(crate) fn ui(ui: &mut Ui, simulation: &mut Simulation) {
+        ComboBox::from_id_source("preset")
+            .selected_text("Preset")
+            .show_ui(ui, |ui| {
+                for preset in Preset::ALL {
+                    if ui.selectable_label(false, preset.name()).clicked() {
+                        preset.apply(simulation);
+                    }
+                }
+            });
+    }
+}
+
+impl FromStr for Preset {
+    type Err = anyhow::Error;
+
+    /// Accepts the name in any case, with or without spaces, dashes or
+    /// underscores between the words
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let wanted = s.replace([' ', '-', '_'], "");
+        Self::ALL
+            .into_iter()
+            .find(|preset| format!("{:?}", preset).eq_ignore_ascii_case(&wanted))
+            .ok_or_else(|| anyhow::anyhow!("Unknown preset '{}'", s))
+    }
+}
@@ -0,0 +1,710 @@
+use crate::analysis::{OrderParameters, Schools};
+use crate::instance::Instance;
+use crate::simulation::TIME_STEP;
+use anyhow::{bail, Context};
+use cgmath::Vector3;
+use egui::{Color32, ComboBox, Ui};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of every binary recording
+pub const BINARY_MAGIC: &[u8; 8] = b"BOIDREC\0";
+/// Bumped whenever the binary layout changes
+pub const BINARY_VERSION: u32 = 1;
+/// Steps buffered before a chunk is written out
+const STEPS_PER_CHUNK: usize = 60;
+
+pub const CSV_HEADER: &str = "id,step,time,x,y,z,vx,vy,vz,species,school";
+pub const ORDER_CSV_HEADER: &str =
+    "step,time,polarization,milling,nearest_neighbour,extent,schools";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordingFormat {
+    /// One row per boid per step, plus a `.order.csv` file with one row per step
+    #[default]
+    Csv,
+    /// Little-endian chunks of steps, see [`BinaryWriter`]
+    Binary,
+}
+
+impl RecordingFormat {
+    pub const ALL: [RecordingFormat; 2] = [RecordingFormat::Csv, RecordingFormat::Binary];
+
+    /// Binary for `.boids` files and CSV for anything else
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension() {
+            Some(x) if x == RecordingFormat::Binary.extension() => RecordingFormat::Binary,
+            _ => RecordingFormat::Csv,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordingFormat::Csv => "csv",
+            RecordingFormat::Binary => "boids",
+        }
+    }
+}
+
+/// Writes the state of every boid after every simulation step
+pub struct Recorder {
+    path: PathBuf,
+    writer: Writer,
+    steps: u64,
+    last_step: Option<u64>,
+}
+
+enum Writer {
+    Csv {
+        boids: BufWriter<File>,
+        order: BufWriter<File>,
+    },
+    Binary(BinaryWriter),
+}
+
+impl Recorder {
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: RecordingFormat,
+        boid_count: usize,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let create = |path: &Path| {
+            File::create(path)
+                .map(BufWriter::new)
+                .with_context(|| format!("Creating {}", path.display()))
+        };
+
+        let writer = match format {
+            RecordingFormat::Csv => {
+                let mut boids = create(&path)?;
+                let mut order = create(&path.with_extension("order.csv"))?;
+                writeln!(boids, "{}", CSV_HEADER)?;
+                writeln!(order, "{}", ORDER_CSV_HEADER)?;
+                Writer::Csv { boids, order }
+            }
+            RecordingFormat::Binary => {
+                Writer::Binary(BinaryWriter::new(create(&path)?, boid_count)?)
+            }
+        };
+
+        Ok(Self {
+            path,
+            writer,
+            steps: 0,
+            last_step: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Number of steps recorded so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Simulation step recorded last, if any
+    pub fn last_step(&self) -> Option<u64> {
+        self.last_step
+    }
+
+    pub fn record(
+        &mut self,
+        step: u64,
+        instances: &[Instance],
+        schools: &Schools,
+        order: &OrderParameters,
+    ) -> anyhow::Result<()> {
+        if let Some(last) = self.last_step.filter(|&x| step <= x) {
+            bail!("Step {} can't be recorded after step {}", step, last);
+        }
+        let time = step as f64 * TIME_STEP as f64;
+        let school = |i: usize| schools.label(i).unwrap_or(0);
+
+        match &mut self.writer {
+            Writer::Csv { boids, order: out } => {
+                for (id, boid) in instances.iter().enumerate() {
+                    let (p, v) = (boid.position, boid.velocity);
+                    writeln!(
+                        boids,
+                        "{},{},{:.6},{},{},{},{},{},{},{},{}",
+                        id,
+                        step,
+                        time,
+                        p.x,
+                        p.y,
+                        p.z,
+                        v.x,
+                        v.y,
+                        v.z,
+                        boid.species,
+                        school(id)
+                    )?;
+                }
+                write_order_row(out, step, order, schools)?;
+            }
+            Writer::Binary(writer) => writer.record(step, instances, &school, order)?,
+        }
+
+        self.steps += 1;
+        self.last_step = Some(step);
+        Ok(())
+    }
+
+    /// Flushes everything still buffered to disk
+    pub fn finish(self) -> anyhow::Result<()> {
+        match self.writer {
+            Writer::Csv {
+                mut boids,
+                mut order,
+            } => {
+                boids.flush()?;
+                order.flush()?;
+            }
+            Writer::Binary(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// Writes one row of the order parameter CSV, see [`ORDER_CSV_HEADER`]
+pub(crate) fn write_order_row(
+    out: &mut impl Write,
+    step: u64,
+    order: &OrderParameters,
+    schools: &Schools,
+) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{},{:.6},{},{},{},{},{}",
+        step,
+        step as f64 * TIME_STEP as f64,
+        order.polarization,
+        order.milling,
+        order.nearest_neighbour,
+        order.extent,
+        schools.clusters.len()
+    )
+}
+
+/// Compact binary recording. All values are little-endian.
+///
+/// The file starts with a header:
+/// `magic: [u8; 8], version: u32, boid_count: u32, time_step: f32`
+///
+/// followed by chunks of up to [`STEPS_PER_CHUNK`] steps:
+/// `step_count: u32` then for every step
+/// `step: u64, polarization: f32, milling: f32, nearest_neighbour: f32, extent: f32`
+/// and for every boid
+/// `position: [f32; 3], velocity: [f32; 3], species: u16, school: u16`.
+///
+/// A chunk is only written once it is complete, so a crash loses at most
+/// the last second of a recording.
+pub struct BinaryWriter {
+    out: BufWriter<File>,
+    boid_count: usize,
+    chunk: Vec<u8>,
+    chunk_steps: u32,
+}
+
+impl BinaryWriter {
+    fn new(mut out: BufWriter<File>, boid_count: usize) -> anyhow::Result<Self> {
+        out.write_all(BINARY_MAGIC)?;
+        out.write_all(&BINARY_VERSION.to_le_bytes())?;
+        let count = u32::try_from(boid_count).context("Too many boids to record")?;
+        out.write_all(&count.to_le_bytes())?;
+        out.write_all(&TIME_STEP.to_le_bytes())?;
+
+        Ok(Self {
+            out,
+            boid_count,
+            chunk: Vec::new(),
+            chunk_steps: 0,
+        })
+    }
+
+    fn record(
+        &mut self,
+        step: u64,
+        instances: &[Instance],
+        school: &dyn Fn(usize) -> usize,
+        order: &OrderParameters,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            instances.len() == self.boid_count,
+            "Recording expects {} boids, got {}",
+            self.boid_count,
+            instances.len()
+        );
+
+        let chunk = &mut self.chunk;
+        chunk.extend_from_slice(&step.to_le_bytes());
+        for value in [
+            order.polarization,
+            order.milling,
+            order.nearest_neighbour,
+            order.extent,
+        ] {
+            chunk.extend_from_slice(&value.to_le_bytes());
+        }
+        for (id, boid) in instances.iter().enumerate() {
+            let p: [f32; 3] = boid.position.into();
+            let v: [f32; 3] = boid.velocity.into();
+            for value in p.into_iter().chain(v) {
+                chunk.extend_from_slice(&value.to_le_bytes());
+            }
+            let species = u16::try_from(boid.species)
+                .with_context(|| format!("Species {} does not fit the recording", boid.species))?;
+            let school = u16::try_from(school(id))
+                .with_context(|| format!("School {} does not fit the recording", school(id)))?;
+            chunk.extend_from_slice(&species.to_le_bytes());
+            chunk.extend_from_slice(&school.to_le_bytes());
+        }
+
+        self.chunk_steps += 1;
+        if self.chunk_steps as usize >= STEPS_PER_CHUNK {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> anyhow::Result<()> {
+        if self.chunk_steps == 0 {
+            return Ok(());
+        }
+        self.out.write_all(&self.chunk_steps.to_le_bytes())?;
+        self.out.write_all(&self.chunk)?;
+        self.out.flush()?;
+        self.chunk.clear();
+        self.chunk_steps = 0;
+        Ok(())
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        self.write_chunk()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordedBoid {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub species: usize,
+}
+
+/// Every boid after one recorded step, ordered by id
+pub struct RecordedFrame {
+    pub step: u64,
+    pub time: f64,
+    pub boids: Vec<RecordedBoid>,
+}
+
+/// A whole recording read back into memory
+pub struct Recording {
+    pub boid_count: usize,
+    /// Frames sorted by step
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl Recording {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+
+        let recording = if bytes.starts_with(BINARY_MAGIC) {
+            Self::from_binary(&bytes)
+        } else {
+            Self::from_csv(std::str::from_utf8(&bytes)?)
+        }
+        .with_context(|| format!("Parsing {}", path.display()))?;
+
+        if recording.frames.is_empty() {
+            bail!("{} has no recorded steps", path.display());
+        }
+        Ok(recording)
+    }
+
+    /// Duration between the first and last frame in seconds
+    pub fn duration(&self) -> f64 {
+        match (self.frames.first(), self.frames.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    /// Reads CSV written by [`Recorder`] or any other tool using the same
+    /// column names. Columns and rows may come in any order and `species`
+    /// is optional. Schools are found again on playback, so `school` is
+    /// ignored.
+    fn from_csv(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text.lines().filter(|x| !x.trim().is_empty());
+        let header = lines.next().context("Missing CSV header")?;
+        let columns = header.split(',').map(str::trim).collect::<Vec<_>>();
+        let column = |name: &str| columns.iter().position(|x| *x == name);
+        let require =
+            |name: &str| column(name).with_context(|| format!("Missing column '{}'", name));
+
+        let id = require("id")?;
+        let step = require("step")?;
+        let time = column("time");
+        let position = [require("x")?, require("y")?, require("z")?];
+        let velocity = [require("vx")?, require("vy")?, require("vz")?];
+        let species = column("species");
+
+        // Rows may come in any order, so group them by step first
+        let mut steps: BTreeMap<u64, (f64, Vec<Option<RecordedBoid>>)> = BTreeMap::new();
+        for (row, line) in lines.enumerate() {
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            let field = |i: usize| {
+                fields
+                    .get(i)
+                    .with_context(|| format!("Row {} is missing a value", row + 2))
+            };
+            let float = |i: usize| -> anyhow::Result<f32> { Ok(field(i)?.parse()?) };
+
+            let boid_step: u64 = field(step)?.parse()?;
+            let boid_id: usize = field(id)?.parse()?;
+            let boid = RecordedBoid {
+                position: Vector3::new(
+                    float(position[0])?,
+                    float(position[1])?,
+                    float(position[2])?,
+                ),
+                velocity: Vector3::new(
+                    float(velocity[0])?,
+                    float(velocity[1])?,
+                    float(velocity[2])?,
+                ),
+                species: match species {
+                    Some(i) => field(i)?.parse()?,
+                    None => 0,
+                },
+            };
+            let boid_time = match time {
+                Some(i) => field(i)?.parse()?,
+                None => boid_step as f64 * TIME_STEP as f64,
+            };
+
+            let (_, boids) = steps
+                .entry(boid_step)
+                .or_insert_with(|| (boid_time, Vec::new()));
+            if boids.len() <= boid_id {
+                boids.resize(boid_id + 1, None);
+            }
+            if boids[boid_id].replace(boid).is_some() {
+                bail!("Boid {} appears twice in step {}", boid_id, boid_step);
+            }
+        }
+
+        let boid_count = steps.values().map(|(_, x)| x.len()).max().unwrap_or(0);
+        let frames = steps
+            .into_iter()
+            .map(|(step, (time, mut boids))| {
+                boids.resize(boid_count, None);
+                let boids = boids
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, boid)| {
+                        boid.with_context(|| format!("Step {} is missing boid {}", step, id))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                Ok(RecordedFrame { step, time, boids })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { boid_count, frames })
+    }
+
+    /// Reads the format written by [`BinaryWriter`]
+    fn from_binary(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = ByteReader { bytes };
+        reader.take(BINARY_MAGIC.len())?;
+        let version = reader.u32()?;
+        if version != BINARY_VERSION {
+            bail!(
+                "Recording version {} is not supported (expected {})",
+                version,
+                BINARY_VERSION
+            );
+        }
+        let boid_count = reader.u32()? as usize;
+        let time_step = reader.f32()? as f64;
+
+        let mut frames = Vec::new();
+        while !reader.bytes.is_empty() {
+            for _ in 0..reader.u32()? {
+                let step = reader.u64()?;
+                // Order parameters are recomputed on playback
+                reader.take(4 * 4)?;
+                let boids = (0..boid_count)
+                    .map(|_| {
+                        let boid = RecordedBoid {
+                            position: Vector3::new(reader.f32()?, reader.f32()?, reader.f32()?),
+                            velocity: Vector3::new(reader.f32()?, reader.f32()?, reader.f32()?),
+                            species: reader.u16()? as usize,
+                        };
+                        // Schools are recomputed on playback too
+                        reader.u16()?;
+                        Ok(boid)
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if let Some(last) = frames.last().filter(|x: &&RecordedFrame| x.step >= step) {
+                    bail!("Step {} comes after step {}", step, last.step);
+                }
+                frames.push(RecordedFrame {
+                    step,
+                    time: step as f64 * time_step,
+                    boids,
+                });
+            }
+        }
+        Ok(Self { boid_count, frames })
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            bail!("Recording ends in the middle of a chunk");
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+/// Start and stop controls for the recording window
+pub(crate) struct RecordingPanel {
+    path: String,
+    format: RecordingFormat,
+    status: Option<anyhow::Result<String>>,
+}
+
+impl RecordingPanel {
+    pub(crate) fn new() -> Self {
+        Self {
+            path: "recording.csv".to_string(),
+            format: RecordingFormat::default(),
+            status: None,
+        }
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui, recorder: &mut Option<Recorder>, boid_count: usize) {
+        match recorder {
+            Some(active) => {
+                ui.label(format!(
+                    "Recording to {} ({} steps)",
+                    active.path().display(),
+                    active.steps()
+                ));
+                if ui.button("⏹ Stop").clicked() {
+                    let active = recorder.take().unwrap();
+                    let steps = active.steps();
+                    self.status = Some(active.finish().map(|_| format!("Wrote {} steps", steps)));
+                }
+            }
+            None => {
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut self.path);
+                });
+                let previous = self.format;
+                ComboBox::from_label("Format")
+                    .selected_text(format!("{:?}", self.format))
+                    .show_ui(ui, |ui| {
+                        for format in RecordingFormat::ALL {
+                            ui.selectable_value(&mut self.format, format, format!("{:?}", format));
+                        }
+                    });
+                if self.format != previous {
+                    self.path = Path::new(&self.path)
+                        .with_extension(self.format.extension())
+                        .display()
+                        .to_string();
+                }
+
+                if ui.button("⏺ Record").clicked() {
+                    match Recorder::create(&self.path, self.format, boid_count) {
+                        Ok(created) => {
+                            *recorder = Some(created);
+                            self.status = None;
+                        }
+                        Err(e) => self.status = Some(Err(e)),
+                    }
+                }
+            }
+        }
+
+        match &self.status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(e)) => {
+                ui.colored_label(Color32::LIGHT_RED, format!("{:#}", e));
+            }
+            None => {}
+        }
+    }
+
+    /// Reports an error that stopped the recording
+    pub(crate) fn set_error(&mut self, error: anyhow::Error) {
+        self.status = Some(Err(error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str, format: RecordingFormat) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("{}-{}", name, std::process::id()))
+            .with_extension(format.extension())
+    }
+
+    fn flock(step: u64) -> Vec<Instance> {
+        (0..3)
+            .map(|i| {
+                let x = (step * 10 + i) as f32;
+                Instance::new(
+                    Vector3::new(x, -x, 0.5 * x),
+                    Vector3::new(1.0, 0.0, -(i as f32)),
+                    i as usize % 2,
+                )
+            })
+            .collect()
+    }
+
+    fn round_trip(format: RecordingFormat) {
+        let path = temp_path("recording-round-trip", format);
+        let (schools, order) = (Schools::new(), OrderParameters::default());
+        let mut recorder = Recorder::create(&path, format, 3).unwrap();
+        // More than a chunk, so the binary format has a partial last chunk
+        let steps = STEPS_PER_CHUNK as u64 + 5;
+        for step in 1..=steps {
+            recorder
+                .record(step, &flock(step), &schools, &order)
+                .unwrap();
+        }
+        recorder.finish().unwrap();
+        let recording = Recording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        if format == RecordingFormat::Csv {
+            std::fs::remove_file(path.with_extension("order.csv")).unwrap();
+        }
+
+        let recording = recording.unwrap();
+        assert_eq!(recording.boid_count, 3);
+        assert_eq!(recording.frames.len(), steps as usize);
+        for (frame, step) in recording.frames.iter().zip(1..) {
+            assert_eq!(frame.step, step);
+            assert!((frame.time - step as f64 * TIME_STEP as f64).abs() < 1e-4);
+            for (boid, instance) in frame.boids.iter().zip(flock(step)) {
+                assert_eq!(boid.position, instance.position);
+                assert_eq!(boid.velocity, instance.velocity);
+                assert_eq!(boid.species, instance.species);
+            }
+        }
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        round_trip(RecordingFormat::Csv);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        round_trip(RecordingFormat::Binary);
+    }
+
+    #[test]
+    fn steps_must_increase() {
+        let path = temp_path("recording-order", RecordingFormat::Binary);
+        let (schools, order) = (Schools::new(), OrderParameters::default());
+        let mut recorder = Recorder::create(&path, RecordingFormat::Binary, 3).unwrap();
+        recorder.record(5, &flock(5), &schools, &order).unwrap();
+        assert!(recorder.record(5, &flock(5), &schools, &order).is_err());
+        assert!(recorder.record(2, &flock(2), &schools, &order).is_err());
+        assert_eq!(recorder.last_step(), Some(5));
+
+        // Write steps out of order past the recorder's check
+        let Writer::Binary(writer) = &mut recorder.writer else {
+            unreachable!()
+        };
+        writer.record(2, &flock(2), &|_| 0, &order).unwrap();
+        recorder.finish().unwrap();
+        let recording = Recording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = format!("{:#}", recording.err().expect("out of order steps loaded"));
+        assert!(error.contains("Step 2 comes after step 5"), "{}", error);
+    }
+
+    #[test]
+    fn csv_rows_in_any_order() {
+        // Grouped by boid rather than by step, with the steps out of order
+        let text = "step,id,x,y,z,vx,vy,vz
+2,0,2,0,0,1,0,0
+1,0,1,0,0,1,0,0
+2,1,2,1,0,1,0,0
+1,1,1,1,0,1,0,0
+";
+        let recording = Recording::from_csv(text).unwrap();
+        assert_eq!(recording.boid_count, 2);
+        let steps = recording.frames.iter().map(|x| x.step).collect::<Vec<_>>();
+        assert_eq!(steps, [1, 2]);
+        for frame in &recording.frames {
+            for (id, boid) in frame.boids.iter().enumerate() {
+                assert_eq!(
+                    boid.position,
+                    Vector3::new(frame.step as f32, id as f32, 0.0)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn csv_missing_boid() {
+        let text = "id,step,x,y,z,vx,vy,vz
+0,1,0,0,0,1,0,0
+1,1,0,0,0,1,0,0
+1,2,0,0,0,1,0,0
+";
+        let error = Recording::from_csv(text)
+            .err()
+            .expect("missing boid loaded");
+        assert_eq!(error.to_string(), "Step 2 is missing boid 0");
+    }
+
+    #[test]
+    fn csv_duplicate_boid() {
+        let text = "id,step,x,y,z,vx,vy,vz
+0,1,0,0,0,1,0,0
+0,1,1,0,0,1,0,0
+";
+        assert!(Recording::from_csv(text).is_err());
+    }
+}
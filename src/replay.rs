@@ -0,0 +1,169 @@
+use crate::instance::Instance;
+use crate::recording::Recording;
+use cgmath::VectorSpace;
+use egui::{Color32, Slider, Ui};
+
+/// Plays a recording back into the tank instead of simulating it
+pub struct Replay {
+    recording: Recording,
+    /// Seconds since the first recorded frame
+    pub time: f64,
+    pub playing: bool,
+    pub speed: f32,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            time: 0.0,
+            playing: true,
+            speed: 1.0,
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.recording.duration()
+    }
+
+    pub fn seek(&mut self, time: f64) {
+        self.time = time.clamp(0.0, self.duration());
+    }
+
+    /// Advances playback by `delta` seconds of wall-clock time
+    pub fn advance(&mut self, delta: f32) {
+        if self.playing {
+            self.seek(self.time + (delta * self.speed) as f64);
+            if self.time >= self.duration() {
+                self.playing = false;
+            }
+        }
+    }
+
+    /// Step of the recorded frame at or just before the playback time
+    pub fn step(&self) -> u64 {
+        let (index, _) = self.frame_at(self.time);
+        self.recording.frames[index].step
+    }
+
+    /// Replaces `instances` with the boids at the current playback time,
+    /// interpolating between recorded frames.
+    pub fn apply(&self, instances: &mut Vec<Instance>) {
+        let (index, alpha) = self.frame_at(self.time);
+        let frames = &self.recording.frames;
+        let from = &frames[index];
+        let to = &frames[(index + 1).min(frames.len() - 1)];
+
+        instances.clear();
+        instances.extend(from.boids.iter().zip(&to.boids).map(|(a, b)| {
+            Instance::new(
+                a.position.lerp(b.position, alpha),
+                a.velocity.lerp(b.velocity, alpha),
+                a.species,
+            )
+        }));
+    }
+
+    /// Index of the last frame at or before `time`, and how far it is
+    /// towards the next frame
+    fn frame_at(&self, time: f64) -> (usize, f32) {
+        let frames = &self.recording.frames;
+        let start = frames[0].time;
+        let index = frames
+            .partition_point(|x| x.time - start <= time)
+            .saturating_sub(1);
+
+        let alpha = match frames.get(index + 1) {
+            Some(next) if next.time > frames[index].time => {
+                ((time + start - frames[index].time) / (next.time - frames[index].time)) as f32
+            }
+            _ => 0.0,
+        };
+        (index, alpha.clamp(0.0, 1.0))
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let label = if self.playing {
+                "⏸ Pause"
+            } else {
+                "▶ Play"
+            };
+            if ui.button(label).clicked() {
+                if !self.playing && self.time >= self.duration() {
+                    self.time = 0.0;
+                }
+                self.playing = !self.playing;
+            }
+            ui.label(format!(
+                "step {}, {} boids",
+                self.step(),
+                self.recording.boid_count
+            ));
+        });
+
+        let mut time = self.time;
+        let duration = self.duration();
+        if ui
+            .add(
+                Slider::new(&mut time, 0.0..=duration)
+                    .suffix("s")
+                    .text("Time"),
+            )
+            .changed()
+        {
+            self.seek(time);
+        }
+        ui.add(
+            Slider::new(&mut self.speed, 0.1..=10.0)
+                .logarithmic(true)
+                .suffix("x")
+                .text("Speed"),
+        );
+    }
+}
+
+/// Controls for loading and playing back recordings
+pub(crate) struct ReplayPanel {
+    path: String,
+    error: Option<anyhow::Error>,
+}
+
+impl ReplayPanel {
+    pub(crate) fn new() -> Self {
+        Self {
+            path: "recording.csv".to_string(),
+            error: None,
+        }
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui, replay: &mut Option<Replay>) {
+        match replay {
+            Some(active) => {
+                active.ui(ui);
+                if ui.button("⏹ Back to simulation").clicked() {
+                    *replay = None;
+                }
+            }
+            None => {
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut self.path);
+                });
+                if ui.button("Load").clicked() {
+                    match Recording::load(&self.path) {
+                        Ok(recording) => {
+                            *replay = Some(Replay::new(recording));
+                            self.error = None;
+                        }
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            }
+        }
+
+        if let Some(e) = &self.error {
+            ui.colored_label(Color32::LIGHT_RED, format!("{:#}", e));
+        }
+    }
+}
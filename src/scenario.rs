@@ -0,0 +1,621 @@
+use crate::camera::Camera;
+use crate::flock::Flock;
+use crate::graphics::MSAA_SAMPLE_COUNT;
+use crate::light::{Lighting, MAX_LIGHTS};
+use crate::preset::Preset;
+use crate::simulation::{Simulation, SimulationModel};
+use crate::skybox::Sky;
+use crate::spawn::{self, Spawner, MAX_BOIDS};
+use crate::{SIZE_X, SIZE_Y};
+use anyhow::{bail, Context};
+use cgmath::Vector3;
+use egui::{Color32, Ui};
+use instant::Instant;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the scenario file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Everything needed to set up a run of the tank, read from a TOML file.
+/// Every field is optional and falls back to the built-in defaults.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    /// Seed for the simulation's random numbers, random when not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Initial conditions file to spawn from instead of `flock`, relative
+    /// to the scenario file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial: Option<PathBuf>,
+    pub flock: Spawner,
+    pub simulation: Simulation,
+    pub render: RenderSettings,
+    pub camera: CameraSettings,
+    pub lighting: Lighting,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    /// Window size in logical pixels
+    pub width: u32,
+    pub height: u32,
+    /// Samples per pixel, 1 to turn multisampling off
+    pub msaa_samples: u32,
+    /// Bundled cube map drawn behind the tank
+    pub sky: Sky,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: SIZE_X,
+            height: SIZE_Y,
+            msaa_samples: MSAA_SAMPLE_COUNT,
+            sky: Sky::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraSettings {
+    pub position: [f32; 3],
+    /// Point the camera looks at
+    pub target: [f32; 3],
+    /// Vertical field of view in degrees
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 10.0, 20.0],
+            target: [0.0, 0.0, 0.0],
+            fovy: 60.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub(crate) fn to_camera(&self, aspect: f32) -> Camera {
+        Camera {
+            eye: self.position.into(),
+            target: self.target.into(),
+            // which way is "up"
+            up: Vector3::unit_y(),
+            aspect,
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+
+    pub(crate) fn apply(&self, camera: &mut Camera) {
+        camera.eye = self.position.into();
+        camera.target = self.target.into();
+        camera.fovy = self.fovy;
+        camera.znear = self.znear;
+        camera.zfar = self.zfar;
+    }
+}
+
+/// Settings given when launching that take precedence over the scenario
+/// file, including after it is reloaded
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// Applied before `model`, so the two can be combined
+    pub preset: Option<Preset>,
+    pub model: Option<SimulationModel>,
+    /// Number of fish to spawn
+    pub count: Option<usize>,
+    pub seed: Option<u64>,
+    /// Window size in logical pixels
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub msaa_samples: Option<u32>,
+}
+
+impl Overrides {
+    pub fn apply(&self, scenario: &mut Scenario) {
+        if let Some(preset) = self.preset {
+            preset.apply(&mut scenario.simulation);
+        }
+        if let Some(model) = self.model {
+            scenario.simulation.model = model;
+        }
+        if let Some(count) = self.count {
+            scenario.flock.count = count;
+        }
+        if let Some(seed) = self.seed {
+            scenario.seed = Some(seed);
+        }
+        let render = &mut scenario.render;
+        render.width = self.width.unwrap_or(render.width);
+        render.height = self.height.unwrap_or(render.height);
+        render.msaa_samples = self.msaa_samples.unwrap_or(render.msaa_samples);
+    }
+
+    /// Checks the overridden values on their own. None of them depend on
+    /// the rest of the scenario, so they stay valid whatever file is loaded.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut scenario = Scenario::default();
+        self.apply(&mut scenario);
+        scenario.validate()
+    }
+}
+
+impl Scenario {
+    /// Reads and validates a scenario file
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        let mut scenario: Scenario =
+            toml::from_str(&text).with_context(|| format!("Parsing {}", path.display()))?;
+        scenario.validate()?;
+
+        if let (Some(initial), Some(dir)) = (&scenario.initial, path.parent()) {
+            scenario.initial = Some(dir.join(initial));
+        }
+        Ok(scenario)
+    }
+
+    /// The scenario at `path` or the defaults, with `overrides` applied
+    pub fn open(path: Option<&Path>, overrides: &Overrides) -> anyhow::Result<Self> {
+        let mut scenario = match path {
+            Some(path) => Scenario::load(path)?,
+            None => Scenario::default(),
+        };
+        overrides.apply(&mut scenario);
+        Ok(scenario)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("Writing {}", path.display()))
+    }
+
+    /// Checks for values the simulation or renderer can't work with,
+    /// reporting all of them at once
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+        let mut check = |ok: bool, message: &str| {
+            if !ok {
+                errors.push(message.to_string());
+            }
+        };
+
+        let flock = &self.flock;
+        check(
+            (1..=MAX_BOIDS).contains(&flock.count),
+            &format!("flock.count must be between 1 and {}", MAX_BOIDS),
+        );
+        check(positive(flock.radius), "flock.radius must be positive");
+        check(non_negative(flock.speed), "flock.speed can't be negative");
+        check(flock.clusters >= 1, "flock.clusters must be at least 1");
+        check(
+            non_negative(flock.cluster_spread),
+            "flock.cluster_spread can't be negative",
+        );
+
+        let simulation = &self.simulation;
+        let limits = &simulation.limits;
+        check(
+            non_negative(limits.min_speed),
+            "simulation.limits.min_speed can't be negative",
+        );
+        check(
+            positive(limits.max_speed) && limits.max_speed >= limits.min_speed,
+            "simulation.limits.max_speed must be positive and at least min_speed",
+        );
+        check(
+            positive(limits.max_acceleration),
+            "simulation.limits.max_acceleration must be positive",
+        );
+        check(
+            positive(limits.max_turn_rate),
+            "simulation.limits.max_turn_rate must be positive",
+        );
+
+        let environment = &simulation.environment;
+        check(
+            non_negative(environment.floor_margin) && non_negative(environment.surface_margin),
+            "simulation.environment margins can't be negative",
+        );
+        check(
+            non_negative(environment.avoidance_strength),
+            "simulation.environment.avoidance_strength can't be negative",
+        );
+        check(
+            !simulation.species.is_empty(),
+            "simulation.species needs at least one species",
+        );
+        for species in &simulation.species {
+            check(
+                non_negative(species.depth[0])
+                    && finite(&species.depth)
+                    && species.depth[0] <= species.depth[1],
+                &format!("species '{}' needs 0 <= depth[0] <= depth[1]", species.name),
+            );
+            check(
+                non_negative(species.depth_strength),
+                &format!(
+                    "species '{}' can't have a negative depth_strength",
+                    species.name
+                ),
+            );
+        }
+
+        let reynolds = &simulation.reynolds;
+        check(
+            positive(reynolds.separation_radius)
+                && positive(reynolds.alignment_radius)
+                && positive(reynolds.cohesion_radius),
+            "simulation.reynolds radii must be positive",
+        );
+        check(
+            non_negative(reynolds.separation_weight)
+                && non_negative(reynolds.alignment_weight)
+                && non_negative(reynolds.cohesion_weight),
+            "simulation.reynolds weights can't be negative",
+        );
+        let vicsek = &simulation.vicsek;
+        check(
+            positive(vicsek.radius),
+            "simulation.vicsek.radius must be positive",
+        );
+        check(
+            (0.0..=1.0).contains(&vicsek.noise),
+            "simulation.vicsek.noise must be between 0 and 1",
+        );
+        check(
+            positive(vicsek.speed),
+            "simulation.vicsek.speed must be positive",
+        );
+        let couzin = &simulation.couzin;
+        check(
+            positive(couzin.repulsion)
+                && non_negative(couzin.orientation_width)
+                && non_negative(couzin.attraction_width),
+            "simulation.couzin zones must have a positive repulsion radius and non-negative widths",
+        );
+        check(
+            (0.0..=360.0).contains(&couzin.field_of_view),
+            "simulation.couzin.field_of_view must be between 0 and 360 degrees",
+        );
+        check(
+            positive(couzin.turning_rate),
+            "simulation.couzin.turning_rate must be positive",
+        );
+        check(
+            positive(couzin.speed),
+            "simulation.couzin.speed must be positive",
+        );
+        check(
+            (0.0..=std::f32::consts::PI).contains(&couzin.noise),
+            "simulation.couzin.noise must be between 0 and π radians",
+        );
+
+        let render = &self.render;
+        check(
+            render.width > 0 && render.height > 0,
+            "render.width and render.height must be positive",
+        );
+        check(
+            matches!(render.msaa_samples, 1 | 4),
+            "render.msaa_samples must be 1 or 4",
+        );
+
+        let camera = &self.camera;
+        check(
+            0.0 < camera.fovy && camera.fovy < 180.0,
+            "camera.fovy must be between 0 and 180 degrees",
+        );
+        check(
+            positive(camera.znear) && camera.znear < camera.zfar && camera.zfar.is_finite(),
+            "camera needs 0 < znear < zfar",
+        );
+        check(
+            finite(&camera.position) && finite(&camera.target),
+            "camera.position and camera.target must be finite",
+        );
+        check(
+            camera.position != camera.target,
+            "camera.position and camera.target must differ",
+        );
+
+        let lighting = &self.lighting;
+        check(
+            lighting.lights.len() <= MAX_LIGHTS,
+            &format!("lighting.lights can have at most {} lights", MAX_LIGHTS),
+        );
+        check(
+            lighting
+                .lights
+                .iter()
+                .all(|light| finite(&light.direction) && light.direction != [0.0; 3]),
+            "lighting.lights directions must be finite and can't be zero",
+        );
+        check(
+            lighting
+                .lights
+                .iter()
+                .flat_map(|light| light.color)
+                .chain(lighting.ambient)
+                .all(non_negative),
+            "lighting colours can't be negative",
+        );
+        check(
+            non_negative(lighting.specular),
+            "lighting.specular can't be negative",
+        );
+        check(
+            positive(lighting.shininess),
+            "lighting.shininess must be positive",
+        );
+
+        if !errors.is_empty() {
+            bail!(errors.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// Applies the flock and simulation settings to `flock`. The flock is
+    /// only respawned when its settings differ from `previous`, so tweaking
+    /// simulation parameters doesn't start the run over.
+    pub fn apply(&self, flock: &mut Flock, previous: Option<&Scenario>) -> anyhow::Result<()> {
+        flock.simulation = self.simulation.clone();
+
+        let respawn = previous.map_or(true, |previous| {
+            previous.seed != self.seed
+                || previous.initial != self.initial
+                || previous.flock != self.flock
+        });
+        if !respawn {
+            return Ok(());
+        }
+
+        if let Some(seed) = self.seed {
+            flock.rng = ChaCha12Rng::seed_from_u64(seed);
+        }
+        flock.spawner = self.flock.clone();
+        let initial = match &self.initial {
+            Some(path) => spawn::load(path)?,
+            None => flock
+                .spawner
+                .generate(flock.simulation.species.len(), &mut flock.rng),
+        };
+        flock.respawn(&initial);
+        Ok(())
+    }
+}
+
+/// Loads the scenario file and reloads it whenever it changes on disk
+pub(crate) struct ScenarioPanel {
+    path: String,
+    overrides: Overrides,
+    /// Scenario last applied, so that reloads only reset what changed
+    current: Option<Scenario>,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+    /// Load on the next poll even if the file hasn't changed
+    force: bool,
+    pub(crate) watch: bool,
+    status: Option<anyhow::Result<String>>,
+}
+
+impl ScenarioPanel {
+    /// Loads the scenario at `path`, if any. Errors are kept for the panel
+    /// to show and the defaults are used instead.
+    pub(crate) fn open(path: Option<PathBuf>, overrides: Overrides) -> (Scenario, Self) {
+        let mut panel = Self {
+            path: path
+                .as_ref()
+                .map_or("scenario.toml".to_string(), |x| x.display().to_string()),
+            overrides,
+            current: None,
+            modified: None,
+            last_check: Instant::now(),
+            force: path.is_some(),
+            watch: path.is_some(),
+            status: None,
+        };
+
+        let scenario = panel.poll().unwrap_or_else(|| {
+            let mut scenario = Scenario::default();
+            panel.overrides.apply(&mut scenario);
+            scenario
+        });
+        (scenario, panel)
+    }
+
+    /// Returns the reloaded scenario if the file changed since the last call
+    /// and is valid
+    pub(crate) fn poll(&mut self) -> Option<Scenario> {
+        let due = self.watch && self.last_check.elapsed() >= WATCH_INTERVAL;
+        if !self.force && !due {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|x| x.modified())
+            .ok();
+        if !self.force && modified == self.modified {
+            return None;
+        }
+        self.force = false;
+        self.modified = modified;
+
+        match Scenario::load(&self.path) {
+            Ok(mut scenario) => {
+                self.overrides.apply(&mut scenario);
+                self.status = Some(Ok(format!("Loaded {}", self.path)));
+                Some(scenario)
+            }
+            Err(e) => {
+                log::error!("Scenario not loaded: {:#}", e);
+                self.status = Some(Err(e));
+                None
+            }
+        }
+    }
+
+    pub(crate) fn current(&self) -> Option<&Scenario> {
+        self.current.as_ref()
+    }
+
+    /// Records `scenario` as applied, returning the one it replaces
+    pub(crate) fn applied(&mut self, scenario: Scenario) -> Option<Scenario> {
+        self.current.replace(scenario)
+    }
+
+    pub(crate) fn set_error(&mut self, error: anyhow::Error) {
+        self.status = Some(Err(error));
+    }
+
+    /// Draws the panel, returning true when the current settings should be
+    /// saved to the scenario file
+    pub(crate) fn ui(&mut self, ui: &mut Ui) -> bool {
+        let mut save = false;
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut self.path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Load").clicked() {
+                self.force = true;
+            }
+            save = ui.button("Save current").clicked();
+            ui.checkbox(&mut self.watch, "Reload on change");
+        });
+
+        match &self.status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(e)) => {
+                ui.colored_label(Color32::LIGHT_RED, format!("{:#}", e));
+            }
+            None => {}
+        }
+        save
+    }
+
+    pub(crate) fn save(&mut self, scenario: &Scenario) {
+        let result = scenario.save(&self.path);
+        // Don't reload what was just written
+        self.modified = std::fs::metadata(&self.path)
+            .and_then(|x| x.modified())
+            .ok();
+        self.status = Some(result.map(|_| format!("Saved {}", self.path)));
+    }
+}
+
+fn finite(values: &[f32]) -> bool {
+    values.iter().all(|x| x.is_finite())
+}
+
+fn non_negative(value: f32) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+fn positive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(scenario: &Scenario) -> String {
+        format!("{:#}", scenario.validate().unwrap_err())
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Scenario::default().validate().unwrap();
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        for preset in Preset::ALL {
+            let mut scenario = Scenario::default();
+            preset.apply(&mut scenario.simulation);
+            scenario.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_numbers_that_are_not_finite() {
+        let mut scenario = Scenario::default();
+        scenario.simulation.couzin.noise = f32::NAN;
+        scenario.simulation.reynolds.cohesion_weight = -1.0;
+        scenario.simulation.environment.avoidance_strength = f32::NAN;
+        scenario.simulation.limits.max_speed = f32::INFINITY;
+        scenario.camera.zfar = f32::INFINITY;
+        let errors = errors(&scenario);
+        for field in [
+            "couzin.noise",
+            "reynolds weights",
+            "avoidance_strength",
+            "max_speed",
+            "zfar",
+        ] {
+            assert!(errors.contains(field), "{} in {}", field, errors);
+        }
+    }
+
+    #[test]
+    fn rejects_negative_speeds() {
+        let mut scenario = Scenario::default();
+        scenario.simulation.vicsek.speed = -1.0;
+        scenario.simulation.couzin.speed = -1.0;
+        scenario.simulation.couzin.turning_rate = -90.0;
+        let errors = errors(&scenario);
+        assert!(errors.contains("vicsek.speed"), "{}", errors);
+        assert!(errors.contains("couzin.speed"), "{}", errors);
+        assert!(errors.contains("couzin.turning_rate"), "{}", errors);
+    }
+
+    #[test]
+    fn rejects_bad_species() {
+        let mut scenario = Scenario::default();
+        let species = &mut scenario.simulation.species[0];
+        species.depth = [5.0, 1.0];
+        species.depth_strength = -0.5;
+        let errors = errors(&scenario);
+        assert!(errors.contains("depth[0] <= depth[1]"), "{}", errors);
+        assert!(errors.contains("depth_strength"), "{}", errors);
+    }
+
+    #[test]
+    fn rejects_no_species() {
+        let mut scenario = Scenario::default();
+        scenario.simulation.species.clear();
+        assert!(errors(&scenario).contains("at least one species"));
+    }
+
+    #[test]
+    fn rejects_bad_flock_and_render() {
+        let mut scenario = Scenario::default();
+        scenario.flock.count = 0;
+        scenario.render.msaa_samples = 2;
+        scenario.camera.znear = -1.0;
+        let errors = errors(&scenario);
+        assert!(errors.contains("flock.count"), "{}", errors);
+        assert!(errors.contains("msaa_samples"), "{}", errors);
+        assert!(errors.contains("znear"), "{}", errors);
+    }
+}
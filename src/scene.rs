@@ -0,0 +1,421 @@
+use crate::bind_group::{create_bind_group, CompactBindGroupDescriptor, CompactBindGroupEntry};
+use crate::boids::Boids;
+use crate::camera::{Camera, CameraUniform};
+use crate::instance::InstanceRaw;
+use crate::light::{Lighting, LightingUniform};
+use crate::mipmaps::generate_mipmaps;
+use crate::model::{DrawModel, Model, Vertex};
+use crate::resources::load_model;
+use crate::scenario::Scenario;
+use crate::shadow::Shadows;
+use crate::skybox::Skybox;
+use crate::texture::Texture;
+use instant::Instant;
+use log::{debug, trace};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayoutDescriptor, CommandEncoder, Device, Queue, TextureFormat, TextureView};
+
+/// The aquarium and the fish swimming in it, drawn into any colour target
+/// of the right format. Shared by the window and offscreen rendering.
+pub(crate) struct Scene {
+    pub(crate) boids: Boids,
+
+    pub(crate) camera: Camera,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+
+    pub(crate) lighting: Lighting,
+    lighting_buffer: wgpu::Buffer,
+    shadows: Shadows,
+
+    pub(crate) skybox: Skybox,
+
+    fish_model: Model,
+    aquarium_model: Model,
+
+    format: TextureFormat,
+    size: (u32, u32),
+    msaa_samples: u32,
+    depth_texture: Texture,
+    multisampled_framebuffer: Texture,
+
+    fish_pipeline_layout: wgpu::PipelineLayout,
+    aquarium_pipeline_layout: wgpu::PipelineLayout,
+    fish_pipeline: wgpu::RenderPipeline,
+    aquarium_pipeline: wgpu::RenderPipeline,
+}
+
+impl Scene {
+    /// Loads the models and builds the pipelines for drawing into targets
+    /// of `format` and `size` in physical pixels, with the camera and
+    /// multisampling of `scenario`
+    pub(crate) async fn new(
+        device: &Device,
+        queue: &Queue,
+        format: TextureFormat,
+        size: (u32, u32),
+        scenario: &Scenario,
+    ) -> anyhow::Result<Self> {
+        // --- Textures ---
+        trace!("Loading textures");
+        let msaa_samples = scenario.render.msaa_samples;
+        let depth_texture =
+            Texture::create_depth_texture(device, size, "depth_texture", msaa_samples);
+        let multisampled_framebuffer =
+            Texture::create_msfb_texture(device, size, format, "mssa_texture", msaa_samples);
+
+        // --- Camera ---
+        let camera = scenario.camera.to_camera(size.0 as f32 / size.1 as f32);
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera);
+
+        // --- Buffers ---
+        trace!("Creating buffers");
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("camera_buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let lighting = scenario.lighting.clone();
+        let lighting_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("lighting_buffer"),
+            contents: bytemuck::cast_slice(&[LightingUniform::new(&lighting)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // --- Bind Groups ---
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            });
+
+        let (camera_bind_group, camera_bind_group_layout) = create_bind_group(
+            device,
+            CompactBindGroupDescriptor {
+                label: Some("camera_bind_group"),
+                entries: &[
+                    CompactBindGroupEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        resource: camera_buffer.as_entire_binding(),
+                        count: None,
+                    },
+                    CompactBindGroupEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        resource: lighting_buffer.as_entire_binding(),
+                        count: None,
+                    },
+                ],
+            },
+        );
+
+        // --- Load models ---
+        let fish_model = load_model("fish.obj", device, queue, &texture_bind_group_layout).await?;
+        let aquarium_model =
+            load_model("aquarium.obj", device, queue, &texture_bind_group_layout).await?;
+
+        let boids = Boids::new(device);
+        let shadows = Shadows::new(device, &lighting);
+        let skybox = Skybox::new(
+            device,
+            queue,
+            scenario.render.sky,
+            &camera,
+            format,
+            msaa_samples,
+        )
+        .await?;
+
+        // --- Render Pipeline ---
+        trace!("Initializing render pipeline");
+        let fish_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("fish_pipeline_layout"),
+            bind_group_layouts: &[
+                &camera_bind_group_layout,
+                &texture_bind_group_layout,
+                &boids.bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let aquarium_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("aquarium_pipeline_layout"),
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &texture_bind_group_layout,
+                    &shadows.bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let (fish_pipeline, aquarium_pipeline) = create_pipelines(
+            device,
+            &fish_pipeline_layout,
+            &aquarium_pipeline_layout,
+            format,
+            msaa_samples,
+        );
+
+        // === Generate mip maps ===
+
+        trace!("Generating mip maps...");
+        let timer = Instant::now();
+
+        let textures: Vec<&Texture> = [&fish_model, &aquarium_model]
+            .iter()
+            .flat_map(|model| {
+                model
+                    .materials
+                    .iter()
+                    .map(|material| &material.diffuse_texture)
+            })
+            .collect();
+        let command_buf = generate_mipmaps(device, &texture_bind_group_layout, &textures);
+        queue.submit([command_buf]);
+
+        debug!("Mip maps generated in {:.2?}", timer.elapsed());
+
+        Ok(Self {
+            boids,
+            camera,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            lighting,
+            lighting_buffer,
+            shadows,
+            skybox,
+            fish_model,
+            aquarium_model,
+            format,
+            size,
+            msaa_samples,
+            depth_texture,
+            multisampled_framebuffer,
+            fish_pipeline_layout,
+            aquarium_pipeline_layout,
+            fish_pipeline,
+            aquarium_pipeline,
+        })
+    }
+
+    pub(crate) fn msaa_samples(&self) -> u32 {
+        self.msaa_samples
+    }
+
+    /// Recreates the depth and multisampled targets for a new target size
+    pub(crate) fn resize(&mut self, device: &Device, size: (u32, u32)) {
+        self.size = size;
+        self.depth_texture =
+            Texture::create_depth_texture(device, size, "depth_texture", self.msaa_samples);
+        self.multisampled_framebuffer = Texture::create_msfb_texture(
+            device,
+            size,
+            self.format,
+            "mssa_texture",
+            self.msaa_samples,
+        );
+    }
+
+    /// Rebuilds the pipelines and targets for a new number of samples per
+    /// pixel
+    pub(crate) fn set_msaa_samples(&mut self, device: &Device, samples: u32) {
+        if samples == self.msaa_samples {
+            return;
+        }
+        self.msaa_samples = samples;
+        (self.fish_pipeline, self.aquarium_pipeline) = create_pipelines(
+            device,
+            &self.fish_pipeline_layout,
+            &self.aquarium_pipeline_layout,
+            self.format,
+            samples,
+        );
+        self.skybox.set_msaa_samples(device, self.format, samples);
+        self.resize(device, self.size);
+    }
+
+    /// Writes the camera as it is now to its uniform buffer
+    pub(crate) fn write_camera(&mut self, queue: &Queue) {
+        self.camera_uniform.update_view_proj(&self.camera);
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+        self.skybox.update(queue, &self.camera);
+    }
+
+    /// Writes the lighting as it is now to its uniform buffers
+    pub(crate) fn write_lighting(&self, queue: &Queue) {
+        queue.write_buffer(
+            &self.lighting_buffer,
+            0,
+            bytemuck::cast_slice(&[LightingUniform::new(&self.lighting)]),
+        );
+        self.shadows.update(queue, &self.lighting);
+    }
+
+    /// Clears `view` and draws the sky, the aquarium and the fish into it,
+    /// with the fish's shadows on the aquarium
+    pub(crate) fn draw(&self, encoder: &mut CommandEncoder, view: &TextureView) {
+        self.shadows.draw(encoder, &self.fish_model, &self.boids);
+
+        let multisampled = self.msaa_samples > 1;
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: if multisampled {
+                    &self.multisampled_framebuffer.view
+                } else {
+                    view
+                },
+                resolve_target: multisampled.then_some(view),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        self.skybox.draw(&mut render_pass);
+
+        render_pass.set_pipeline(&self.aquarium_pipeline);
+        render_pass.set_bind_group(2, &self.shadows.bind_group, &[]);
+        render_pass.draw_model_instanced(&self.aquarium_model, 0..1, &self.camera_bind_group);
+
+        render_pass.set_vertex_buffer(1, self.boids.buffer.slice(..));
+        render_pass.set_bind_group(2, &self.boids.bind_group, &[]);
+        render_pass.set_pipeline(&self.fish_pipeline);
+        render_pass.draw_model_instanced(
+            &self.fish_model,
+            0..self.boids.flock.instances.len() as u32,
+            &self.camera_bind_group,
+        );
+    }
+}
+
+fn create_pipelines(
+    device: &Device,
+    fish_layout: &wgpu::PipelineLayout,
+    aquarium_layout: &wgpu::PipelineLayout,
+    format: TextureFormat,
+    sample_count: u32,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let fish_pipeline = create_render_pipeline(
+        device,
+        fish_layout,
+        format,
+        Some(Texture::DEPTH_FORMAT),
+        &[Vertex::desc(), InstanceRaw::desc()],
+        wgpu::include_wgsl!("shaders/fish.wgsl"),
+        sample_count,
+    );
+    let aquarium_pipeline = create_render_pipeline(
+        device,
+        aquarium_layout,
+        format,
+        Some(Texture::DEPTH_FORMAT),
+        &[Vertex::desc()],
+        wgpu::include_wgsl!("shaders/aquarium.wgsl"),
+        sample_count,
+    );
+    (fish_pipeline, aquarium_pipeline)
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(shader);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState {
+                    alpha: wgpu::BlendComponent::REPLACE,
+                    color: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -1,7 +1,22 @@
 struct CameraUniform {
+    view_position: vec4<f32>,
     view_proj: mat4x4<f32>,
 };
 
+struct Light {
+    // Direction the light travels in
+    direction: vec3<f32>,
+    color: vec3<f32>,
+};
+
+struct Lighting {
+    lights: array<Light, 4>,
+    ambient: vec3<f32>,
+    count: u32,
+    specular: f32,
+    shininess: f32,
+};
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
@@ -10,11 +25,15 @@ struct VertexInput {
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) world_normal: vec3<f32>,
 };
 
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> lighting: Lighting;
 
 @vertex
 fn vs_main(
@@ -24,6 +43,8 @@ fn vs_main(
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
     out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.world_position = model.position;
+    out.world_normal = model.normal;
     return out;
 }
 
@@ -34,7 +55,65 @@ var t_diffuse: texture_2d<f32>;
 @group(1) @binding(1)
 var s_diffuse: sampler;
 
+struct Shadow {
+    view_proj: mat4x4<f32>,
+    enabled: u32,
+};
+
+@group(2) @binding(0)
+var<uniform> shadow: Shadow;
+@group(2) @binding(1)
+var shadow_map: texture_depth_2d;
+@group(2) @binding(2)
+var shadow_sampler: sampler_comparison;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    // The tank is seen from inside, so light the side facing the camera
+    let view_dir = camera.view_position.xyz - in.world_position;
+    let normal = faceForward(in.world_normal, -view_dir, in.world_normal);
+    let lit = shadow_factor(in.world_position);
+    return vec4(shade(color.xyz, in.world_position, normal, lit), color.a);
+}
+
+// How much of the main light reaches `world_position` past the fish, from
+// 0 in full shadow to 1, softened over the neighbouring texels
+fn shadow_factor(world_position: vec3<f32>) -> f32 {
+    if (shadow.enabled == 0u) {
+        return 1.0;
+    }
+    let light_space = shadow.view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    var lit = 0.0;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Blinn-Phong shading of a surface of colour `albedo`, with `main_light`
+// of the first light getting through
+fn shade(albedo: vec3<f32>, world_position: vec3<f32>, world_normal: vec3<f32>, main_light: f32) -> vec3<f32> {
+    let normal = normalize(world_normal);
+    let view_dir = normalize(camera.view_position.xyz - world_position);
+    var color = lighting.ambient * albedo;
+    for (var i = 0u; i < lighting.count; i += 1u) {
+        let light = lighting.lights[i];
+        if (length(light.direction) < 0.0001) {
+            continue;
+        }
+        let light_dir = -normalize(light.direction);
+        let half_dir = normalize(view_dir + light_dir);
+        let diffuse = max(dot(normal, light_dir), 0.0);
+        let specular = pow(max(dot(normal, half_dir), 0.0), lighting.shininess) * lighting.specular;
+        let visible = select(1.0, main_light, i == 0u);
+        color += light.color * visible * (albedo * diffuse + select(0.0, specular, diffuse > 0.0));
+    }
+    return color;
+}
@@ -1,7 +1,22 @@
 struct CameraUniform {
+    view_position: vec4<f32>,
     view_proj: mat4x4<f32>,
 };
 
+struct Light {
+    // Direction the light travels in
+    direction: vec3<f32>,
+    color: vec3<f32>,
+};
+
+struct Lighting {
+    lights: array<Light, 4>,
+    ambient: vec3<f32>,
+    count: u32,
+    specular: f32,
+    shininess: f32,
+};
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
@@ -22,10 +37,14 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) index: u32,
+    @location(2) world_position: vec3<f32>,
+    @location(3) world_normal: vec3<f32>,
 };
 
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> lighting: Lighting;
 
 @vertex
 fn vs_main(
@@ -39,10 +58,15 @@ fn vs_main(
         instance.model_matrix_3,
     );
 
+    // Fish are only moved and turned, so normals transform like directions
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
     out.index = instance.index;
+    out.world_position = world_position.xyz;
+    out.world_normal = (model_matrix * vec4<f32>(model.normal, 0.0)).xyz;
     return out;
 }
 
@@ -54,14 +78,33 @@ var t_diffuse: texture_2d<f32>;
 var s_diffuse: sampler;
 
 @group(2) @binding(0)
-var<storage, read> tints: array<vec3<f32>, 50>;
-
-const TINT = 0.05;
+var<storage, read> tints: array<vec4<f32>>;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let tnt_color = tints[in.index] * TINT;
+    // Tint colour in xyz, and how much it replaces the texture in w
+    let tint = tints[in.index];
+    let tnt_color = tint.xyz * tint.w;
     let tex_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    let fin_color = tex_color.xyz * (1.0 - TINT) + tnt_color;
-    return vec4(fin_color, 1.0);
-}
\ No newline at end of file
+    let fin_color = tex_color.xyz * (1.0 - tint.w) + tnt_color;
+    return vec4(shade(fin_color, in.world_position, in.world_normal), 1.0);
+}
+
+// Blinn-Phong shading of a surface of colour `albedo`
+fn shade(albedo: vec3<f32>, world_position: vec3<f32>, world_normal: vec3<f32>) -> vec3<f32> {
+    let normal = normalize(world_normal);
+    let view_dir = normalize(camera.view_position.xyz - world_position);
+    var color = lighting.ambient * albedo;
+    for (var i = 0u; i < lighting.count; i += 1u) {
+        let light = lighting.lights[i];
+        if (length(light.direction) < 0.0001) {
+            continue;
+        }
+        let light_dir = -normalize(light.direction);
+        let half_dir = normalize(view_dir + light_dir);
+        let diffuse = max(dot(normal, light_dir), 0.0);
+        let specular = pow(max(dot(normal, half_dir), 0.0), lighting.shininess) * lighting.specular;
+        color += light.color * (albedo * diffuse + select(0.0, specular, diffuse > 0.0));
+    }
+    return color;
+}
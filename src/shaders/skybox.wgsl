@@ -0,0 +1,42 @@
+struct Sky {
+    // From clip space to directions in the world
+    inv_view_proj: mat4x4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> sky: Sky;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // One triangle covering the whole screen, on the far plane
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+// === Fragment ===
+
+@group(1) @binding(0)
+var t_sky: texture_cube<f32>;
+@group(1) @binding(1)
+var s_sky: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The eye is at the origin, so the point on the near plane gives the
+    // direction of the ray through this pixel
+    let world = sky.inv_view_proj * vec4<f32>(in.ndc, 0.0, 1.0);
+    let direction = world.xyz / world.w;
+    // Cube maps are looked up left-handed, so +Z is in front of the camera
+    // looking down -z
+    return textureSample(t_sky, s_sky, vec3<f32>(direction.x, direction.y, -direction.z));
+}
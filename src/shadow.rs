@@ -0,0 +1,219 @@
+use crate::bind_group::{create_bind_group, CompactBindGroupDescriptor, CompactBindGroupEntry};
+use crate::boids::{Boids, AQUARIUM_FLOOR, AQUARIUM_RADIUS, AQUARIUM_SURFACE};
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::instance::InstanceRaw;
+use crate::light::Lighting;
+use crate::model::{Model, Vertex};
+use crate::texture::Texture;
+use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Vector3};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, CommandEncoder, Device, Queue};
+
+/// Width and height of the shadow map in texels
+const SHADOW_MAP_SIZE: u32 = 2048;
+
+/// [`Shadows`] as laid out in the `Shadow` struct of the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    /// From world space to the shadow map
+    view_proj: [[f32; 4]; 4],
+    /// 0 when the main light casts no shadows
+    enabled: u32,
+    _padding: [u32; 3],
+}
+
+/// Depth of the fish as seen from the main light, for the aquarium to
+/// tell which parts of it are in the school's shadow
+pub(crate) struct Shadows {
+    map: Texture,
+    uniform_buffer: wgpu::Buffer,
+    /// Just the uniform, for rendering into the map
+    pass_bind_group: wgpu::BindGroup,
+    /// The uniform with the map and its comparison sampler, for sampling it
+    pub(crate) bind_group: wgpu::BindGroup,
+    pub(crate) bind_group_layout: BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Shadows {
+    pub(crate) fn new(device: &Device, lighting: &Lighting) -> Self {
+        let map = Texture::create_depth_texture(
+            device,
+            (SHADOW_MAP_SIZE, SHADOW_MAP_SIZE),
+            "shadow_map",
+            1,
+        );
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("shadow_buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform::new(lighting)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_entry = |visibility| CompactBindGroupEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            resource: uniform_buffer.as_entire_binding(),
+            count: None,
+        };
+        let (pass_bind_group, pass_bind_group_layout) = create_bind_group(
+            device,
+            CompactBindGroupDescriptor {
+                label: Some("shadow_pass_bind_group"),
+                entries: &[uniform_entry(wgpu::ShaderStages::VERTEX)],
+            },
+        );
+        let (bind_group, bind_group_layout) = create_bind_group(
+            device,
+            CompactBindGroupDescriptor {
+                label: Some("shadow_bind_group"),
+                entries: &[
+                    uniform_entry(wgpu::ShaderStages::FRAGMENT),
+                    CompactBindGroupEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                        },
+                        resource: wgpu::BindingResource::TextureView(&map.view),
+                        count: None,
+                    },
+                    CompactBindGroupEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        resource: wgpu::BindingResource::Sampler(
+                            map.sampler.as_ref().expect("Depth textures have a sampler"),
+                        ),
+                        count: None,
+                    },
+                ],
+            },
+        );
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shadow_pipeline_layout"),
+            bind_group_layouts: &[&pass_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/shadow.wgsl"));
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shadow_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            },
+            // Only depth is written
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                // Fish are thin, so both sides have to cast shadows
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                // Keeps surfaces from shadowing themselves
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            map,
+            uniform_buffer,
+            pass_bind_group,
+            bind_group,
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    /// Points the shadow map along the main light as it is now
+    pub(crate) fn update(&self, queue: &Queue, lighting: &Lighting) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[ShadowUniform::new(lighting)]),
+        );
+    }
+
+    /// Renders the fish into the shadow map
+    pub(crate) fn draw(&self, encoder: &mut CommandEncoder, fish_model: &Model, boids: &Boids) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("shadow_pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.map.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.pass_bind_group, &[]);
+        pass.set_vertex_buffer(1, boids.buffer.slice(..));
+        let instances = 0..boids.flock.instances.len() as u32;
+        for mesh in &fish_model.meshes {
+            pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+        }
+    }
+}
+
+impl ShadowUniform {
+    fn new(lighting: &Lighting) -> Self {
+        let light = lighting
+            .lights
+            .first()
+            .filter(|light| lighting.shadows && light.direction != [0.0; 3]);
+        let view_proj = match light {
+            Some(light) => light_view_projection(light.direction.into()),
+            None => Matrix4::identity(),
+        };
+        Self {
+            view_proj: view_proj.into(),
+            enabled: light.is_some() as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Orthographic view along `direction` just covering the whole tank
+fn light_view_projection(direction: Vector3<f32>) -> Matrix4<f32> {
+    let centre = Point3::new(0.0, (AQUARIUM_FLOOR + AQUARIUM_SURFACE) * 0.5, 0.0);
+    let half_height = (AQUARIUM_SURFACE - AQUARIUM_FLOOR) * 0.5;
+    let radius = Vector3::new(AQUARIUM_RADIUS, half_height, AQUARIUM_RADIUS).magnitude();
+
+    let direction = direction.normalize();
+    let up = if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let eye = centre - direction * (2.0 * radius);
+    let view = Matrix4::look_at_rh(eye, centre, up);
+    let proj = cgmath::ortho(-radius, radius, -radius, radius, radius, 3.0 * radius);
+    OPENGL_TO_WGPU_MATRIX * proj * view
+}
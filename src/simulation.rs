@@ -0,0 +1,846 @@
+use crate::boids::{AQUARIUM_FLOOR, AQUARIUM_RADIUS, AQUARIUM_SURFACE};
+use crate::instance::Instance;
+use crate::spatial::SpatialGrid;
+use cgmath::{InnerSpace, Vector3, Zero};
+use egui::{ComboBox, Slider, TextEdit, Ui};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+/// Fixed simulation time step in seconds
+pub const TIME_STEP: f32 = 1.0 / 60.0;
+
+/// How far from the glass boids start turning back into the tank
+const WALL_MARGIN: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SimulationModel {
+    /// Separation, alignment and cohesion steering (Reynolds, 1987)
+    #[default]
+    Reynolds,
+    /// Constant speed particles aligning to their neighbours under angular noise
+    Vicsek,
+    /// Zones of repulsion, orientation and attraction (Couzin et al., 2002)
+    Couzin,
+}
+
+impl SimulationModel {
+    pub const ALL: [SimulationModel; 3] = [
+        SimulationModel::Reynolds,
+        SimulationModel::Vicsek,
+        SimulationModel::Couzin,
+    ];
+}
+
+impl FromStr for SimulationModel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|model| format!("{:?}", model).eq_ignore_ascii_case(s))
+            .ok_or_else(|| anyhow::anyhow!("Unknown simulation model '{}'", s))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Reynolds {
+    pub separation_radius: f32,
+    pub separation_weight: f32,
+    pub alignment_radius: f32,
+    pub alignment_weight: f32,
+    pub cohesion_radius: f32,
+    pub cohesion_weight: f32,
+}
+
+impl Default for Reynolds {
+    fn default() -> Self {
+        Self {
+            separation_radius: 1.5,
+            separation_weight: 4.0,
+            alignment_radius: 4.0,
+            alignment_weight: 1.0,
+            cohesion_radius: 6.0,
+            cohesion_weight: 0.5,
+        }
+    }
+}
+
+impl Reynolds {
+    fn radius(&self) -> f32 {
+        self.separation_radius
+            .max(self.alignment_radius)
+            .max(self.cohesion_radius)
+    }
+
+    fn step(&self, instances: &mut [Instance], dt: f32) {
+        let grid = SpatialGrid::new(self.radius(), instances.iter().map(|x| x.position));
+
+        let accelerations = instances
+            .iter()
+            .enumerate()
+            .map(|(i, boid)| {
+                let mut separation = Vector3::zero();
+                let (mut heading, mut aligned) = (Vector3::zero(), 0);
+                let (mut centre, mut cohesive) = (Vector3::zero(), 0);
+
+                grid.for_each_within(boid.position, self.radius(), |j| {
+                    if j == i {
+                        return;
+                    }
+                    let other = &instances[j];
+                    let offset = boid.position - other.position;
+                    let distance = offset.magnitude();
+                    if distance < self.separation_radius && distance > 0.0 {
+                        separation += offset / (distance * distance);
+                    }
+                    if distance < self.alignment_radius {
+                        heading += other.velocity;
+                        aligned += 1;
+                    }
+                    if distance < self.cohesion_radius {
+                        centre += other.position;
+                        cohesive += 1;
+                    }
+                });
+
+                let mut acceleration = separation * self.separation_weight;
+                if aligned > 0 {
+                    acceleration +=
+                        (heading / aligned as f32 - boid.velocity) * self.alignment_weight;
+                }
+                if cohesive > 0 {
+                    acceleration +=
+                        (centre / cohesive as f32 - boid.position) * self.cohesion_weight;
+                }
+                acceleration + wall_avoidance(boid.position)
+            })
+            .collect::<Vec<_>>();
+
+        for (boid, acceleration) in instances.iter_mut().zip(accelerations) {
+            boid.velocity += acceleration * dt;
+        }
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.add(Slider::new(&mut self.separation_radius, 0.1..=10.0).text("Separation radius"));
+        ui.add(Slider::new(&mut self.separation_weight, 0.0..=10.0).text("Separation weight"));
+        ui.add(Slider::new(&mut self.alignment_radius, 0.1..=10.0).text("Alignment radius"));
+        ui.add(Slider::new(&mut self.alignment_weight, 0.0..=10.0).text("Alignment weight"));
+        ui.add(Slider::new(&mut self.cohesion_radius, 0.1..=10.0).text("Cohesion radius"));
+        ui.add(Slider::new(&mut self.cohesion_weight, 0.0..=10.0).text("Cohesion weight"));
+    }
+}
+
+/// Vicsek et al. (1995), in three dimensions
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Vicsek {
+    pub radius: f32,
+    pub speed: f32,
+    /// Noise amplitude η: headings are perturbed within a cone of half angle ηπ
+    pub noise: f32,
+}
+
+impl Default for Vicsek {
+    fn default() -> Self {
+        Self {
+            radius: 3.0,
+            speed: 4.0,
+            noise: 0.1,
+        }
+    }
+}
+
+impl Vicsek {
+    fn step<R: Rng + ?Sized>(&self, instances: &mut [Instance], rng: &mut R) {
+        let grid = SpatialGrid::new(self.radius, instances.iter().map(|x| x.position));
+
+        let headings = instances
+            .iter()
+            .map(|boid| {
+                // The neighbourhood includes the boid itself
+                let mut heading = Vector3::zero();
+                grid.for_each_within(boid.position, self.radius, |j| {
+                    let velocity = instances[j].velocity;
+                    if velocity.magnitude2() > 0.0 {
+                        heading += velocity.normalize();
+                    }
+                });
+                heading
+            })
+            .collect::<Vec<_>>();
+
+        for (boid, heading) in instances.iter_mut().zip(headings) {
+            let heading = if heading.magnitude2() > 0.0 {
+                heading.normalize()
+            } else {
+                random_unit_vector(rng)
+            };
+            boid.velocity = perturb(heading, self.noise * PI, rng) * self.speed;
+        }
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.add(Slider::new(&mut self.radius, 0.1..=10.0).text("Interaction radius"));
+        ui.add(Slider::new(&mut self.speed, 0.1..=20.0).text("Speed"));
+        ui.add(Slider::new(&mut self.noise, 0.0..=1.0).text("Noise η"));
+    }
+}
+
+/// Couzin et al. (2002). The orientation and attraction zones are given as
+/// widths, so they stay nested around the zone of repulsion while sweeping.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Couzin {
+    /// Radius of the zone of repulsion
+    pub repulsion: f32,
+    /// Width of the zone of orientation, Δr_o
+    pub orientation_width: f32,
+    /// Width of the zone of attraction, Δr_a
+    pub attraction_width: f32,
+    /// Field of perception in degrees; the rest behind the boid is blind
+    pub field_of_view: f32,
+    /// Maximum turning rate in degrees per second
+    pub turning_rate: f32,
+    pub speed: f32,
+    /// Headings are perturbed within a cone of this half angle in radians
+    pub noise: f32,
+}
+
+impl Default for Couzin {
+    fn default() -> Self {
+        Self {
+            repulsion: 1.0,
+            orientation_width: 2.0,
+            attraction_width: 8.0,
+            field_of_view: 270.0,
+            turning_rate: 120.0,
+            speed: 3.0,
+            noise: 0.05,
+        }
+    }
+}
+
+/// Zones of the Couzin model around a boid, from the inside out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Zone {
+    Repulsion,
+    Orientation,
+    Attraction,
+}
+
+impl Couzin {
+    fn radius(&self) -> f32 {
+        self.repulsion + self.orientation_width + self.attraction_width
+    }
+
+    /// Cosine of half the field of view, for comparing against headings
+    fn half_view_cos(&self) -> f32 {
+        (self.field_of_view.to_radians() / 2.0).cos()
+    }
+
+    /// Zone `other` is in as seen by `boid`, or `None` when `boid` doesn't
+    /// react to it because it is out of range or in the blind angle
+    pub(crate) fn zone(&self, boid: &Instance, other: &Instance) -> Option<Zone> {
+        let heading = normalize_or(boid.velocity, Vector3::unit_x());
+        self.zone_of(
+            heading,
+            self.half_view_cos(),
+            other.position - boid.position,
+        )
+        .map(|(zone, _)| zone)
+    }
+
+    /// Zone of a neighbour at `offset` from a boid with the given `heading`,
+    /// along with the direction towards it
+    fn zone_of(
+        &self,
+        heading: Vector3<f32>,
+        half_view_cos: f32,
+        offset: Vector3<f32>,
+    ) -> Option<(Zone, Vector3<f32>)> {
+        let distance = offset.magnitude();
+        if distance == 0.0 || distance > self.radius() {
+            return None;
+        }
+        let direction = offset / distance;
+        if heading.dot(direction) < half_view_cos {
+            return None;
+        }
+
+        let zone = if distance < self.repulsion {
+            Zone::Repulsion
+        } else if distance < self.repulsion + self.orientation_width {
+            Zone::Orientation
+        } else {
+            Zone::Attraction
+        };
+        Some((zone, direction))
+    }
+
+    fn step<R: Rng + ?Sized>(&self, instances: &mut [Instance], rng: &mut R, dt: f32) {
+        let grid = SpatialGrid::new(self.radius(), instances.iter().map(|x| x.position));
+        let half_view_cos = self.half_view_cos();
+
+        let desired = instances
+            .iter()
+            .enumerate()
+            .map(|(i, boid)| {
+                let heading = normalize_or(boid.velocity, Vector3::unit_x());
+                let mut repulse = Vector3::zero();
+                let mut orient = Vector3::zero();
+                let mut attract = Vector3::zero();
+                let (mut repulsed, mut oriented, mut attracted) = (false, false, false);
+
+                grid.for_each_within(boid.position, self.radius(), |j| {
+                    if j == i {
+                        return;
+                    }
+                    let offset = instances[j].position - boid.position;
+                    match self.zone_of(heading, half_view_cos, offset) {
+                        Some((Zone::Repulsion, direction)) => {
+                            repulse -= direction;
+                            repulsed = true;
+                        }
+                        Some((Zone::Orientation, _)) => {
+                            orient += normalize_or(instances[j].velocity, Vector3::zero());
+                            oriented = true;
+                        }
+                        Some((Zone::Attraction, direction)) => {
+                            attract += direction;
+                            attracted = true;
+                        }
+                        None => {}
+                    }
+                });
+
+                // Repulsion takes priority over the other zones
+                let direction = if repulsed {
+                    repulse
+                } else {
+                    // A boid orients to its own heading as well
+                    let orient = normalize_or(orient + heading, heading);
+                    let attract = normalize_or(attract, heading);
+                    match (oriented, attracted) {
+                        (true, true) => (orient + attract) / 2.0,
+                        (true, false) => orient,
+                        (false, true) => attract,
+                        (false, false) => heading,
+                    }
+                };
+                let direction = normalize_or(direction, heading);
+                let direction = perturb(direction, self.noise, rng);
+
+                turn_towards(heading, direction, self.turning_rate.to_radians() * dt)
+            })
+            .collect::<Vec<_>>();
+
+        for (boid, heading) in instances.iter_mut().zip(desired) {
+            boid.velocity = heading * self.speed;
+        }
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.add(Slider::new(&mut self.repulsion, 0.1..=5.0).text("Repulsion radius"));
+        ui.add(Slider::new(&mut self.orientation_width, 0.0..=15.0).text("Orientation width"));
+        ui.add(Slider::new(&mut self.attraction_width, 0.0..=15.0).text("Attraction width"));
+        ui.add(Slider::new(&mut self.field_of_view, 0.0..=360.0).text("Field of view °"));
+        ui.add(Slider::new(&mut self.turning_rate, 1.0..=720.0).text("Turning rate °/s"));
+        ui.add(Slider::new(&mut self.speed, 0.1..=20.0).text("Speed"));
+        ui.add(Slider::new(&mut self.noise, 0.0..=1.0).text("Noise (rad)"));
+    }
+}
+
+/// Limits on how boids may move, applied after the model has produced their
+/// new velocities. Vicsek and Couzin boids swim at their model's speed
+/// clamped into `min_speed..=max_speed`, and turn no faster than either
+/// their own turning rate or `max_turn_rate`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Kinematics {
+    pub min_speed: f32,
+    pub max_speed: f32,
+    /// Largest change in velocity per second
+    pub max_acceleration: f32,
+    /// Largest change in heading in degrees per second
+    pub max_turn_rate: f32,
+}
+
+impl Default for Kinematics {
+    fn default() -> Self {
+        Self {
+            min_speed: 1.0,
+            max_speed: 8.0,
+            max_acceleration: 20.0,
+            max_turn_rate: 360.0,
+        }
+    }
+}
+
+impl Kinematics {
+    fn apply(&self, previous: Vector3<f32>, velocity: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        let speed = velocity.magnitude();
+        let mut velocity = velocity;
+
+        if previous.magnitude2() > 0.0 && speed > 0.0 {
+            let heading = turn_towards(
+                previous.normalize(),
+                velocity / speed,
+                self.max_turn_rate.to_radians() * dt,
+            );
+            velocity = heading * speed;
+        }
+
+        let change = velocity - previous;
+        let max_change = self.max_acceleration * dt;
+        if change.magnitude() > max_change {
+            velocity = previous + change.normalize_to(max_change);
+        }
+
+        let speed = velocity.magnitude();
+        if speed > self.max_speed {
+            velocity.normalize_to(self.max_speed)
+        } else if speed < self.min_speed {
+            normalize_or(velocity, normalize_or(previous, Vector3::unit_x())) * self.min_speed
+        } else {
+            velocity
+        }
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.add(Slider::new(&mut self.min_speed, 0.0..=20.0).text("Min speed"));
+        ui.add(Slider::new(&mut self.max_speed, 0.1..=20.0).text("Max speed"));
+        ui.add(Slider::new(&mut self.max_acceleration, 0.1..=100.0).text("Max acceleration"));
+        ui.add(Slider::new(&mut self.max_turn_rate, 1.0..=720.0).text("Max turn rate °/s"));
+        self.max_speed = self.max_speed.max(self.min_speed);
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Species {
+    pub name: String,
+    /// Preferred band of depths below the water surface, shallowest first
+    pub depth: [f32; 2],
+    /// Strength of the vertical pull back into the preferred band
+    pub depth_strength: f32,
+}
+
+impl Species {
+    fn ui(&mut self, ui: &mut Ui) {
+        let max_depth = AQUARIUM_SURFACE - AQUARIUM_FLOOR;
+        ui.add(TextEdit::singleline(&mut self.name).desired_width(120.0));
+        ui.add(Slider::new(&mut self.depth[0], 0.0..=max_depth).text("Min depth"));
+        ui.add(Slider::new(&mut self.depth[1], 0.0..=max_depth).text("Max depth"));
+        ui.add(Slider::new(&mut self.depth_strength, 0.0..=5.0).text("Depth strength"));
+        self.depth[1] = self.depth[1].max(self.depth[0]);
+    }
+}
+
+/// Forces the tank itself applies to every boid
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Environment {
+    /// Distance from the floor at which boids start avoiding it
+    pub floor_margin: f32,
+    /// Distance from the surface at which boids start avoiding it
+    pub surface_margin: f32,
+    pub avoidance_strength: f32,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            floor_margin: 2.0,
+            surface_margin: 3.0,
+            avoidance_strength: 20.0,
+        }
+    }
+}
+
+impl Environment {
+    fn vertical_acceleration(&self, species: Option<&Species>, height: f32) -> f32 {
+        let mut acceleration = 0.0;
+
+        if let Some(species) = species {
+            let depth = AQUARIUM_SURFACE - height;
+            if depth < species.depth[0] {
+                acceleration -= (species.depth[0] - depth) * species.depth_strength;
+            } else if depth > species.depth[1] {
+                acceleration += (depth - species.depth[1]) * species.depth_strength;
+            }
+        }
+
+        let above_floor = height - AQUARIUM_FLOOR;
+        if above_floor < self.floor_margin {
+            acceleration += (1.0 - above_floor / self.floor_margin) * self.avoidance_strength;
+        }
+        let below_surface = AQUARIUM_SURFACE - height;
+        if below_surface < self.surface_margin {
+            acceleration -= (1.0 - below_surface / self.surface_margin) * self.avoidance_strength;
+        }
+
+        acceleration
+    }
+
+    fn ui(&mut self, ui: &mut Ui) {
+        ui.add(Slider::new(&mut self.floor_margin, 0.1..=10.0).text("Floor margin"));
+        ui.add(Slider::new(&mut self.surface_margin, 0.1..=10.0).text("Surface margin"));
+        ui.add(Slider::new(&mut self.avoidance_strength, 0.0..=50.0).text("Avoidance"));
+    }
+}
+
+/// Selected model and the parameters of every model
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Simulation {
+    pub model: SimulationModel,
+    pub limits: Kinematics,
+    pub environment: Environment,
+    pub species: Vec<Species>,
+    pub reynolds: Reynolds,
+    pub vicsek: Vicsek,
+    pub couzin: Couzin,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self {
+            model: SimulationModel::default(),
+            limits: Kinematics::default(),
+            environment: Environment::default(),
+            species: vec![
+                Species {
+                    name: "Mid-water".to_string(),
+                    depth: [12.0, 28.0],
+                    depth_strength: 0.5,
+                },
+                Species {
+                    name: "Bottom".to_string(),
+                    depth: [30.0, 37.0],
+                    depth_strength: 0.5,
+                },
+            ],
+            reynolds: Reynolds::default(),
+            vicsek: Vicsek::default(),
+            couzin: Couzin::default(),
+        }
+    }
+}
+
+impl Simulation {
+    pub fn new(model: SimulationModel) -> Self {
+        Self {
+            model,
+            ..Default::default()
+        }
+    }
+
+    /// Furthest distance at which the current model lets boids react to
+    /// each other
+    pub fn interaction_radius(&self) -> f32 {
+        match self.model {
+            SimulationModel::Reynolds => self.reynolds.radius(),
+            SimulationModel::Vicsek => self.vicsek.radius,
+            SimulationModel::Couzin => self.couzin.radius(),
+        }
+    }
+
+    /// Whether `boid` reacts to `other` under the current model. Couzin
+    /// boids don't see behind them, the other models only look at distance.
+    pub(crate) fn perceives(&self, boid: &Instance, other: &Instance) -> bool {
+        match self.model {
+            SimulationModel::Couzin => self.couzin.zone(boid, other).is_some(),
+            _ => {
+                let radius = self.interaction_radius();
+                (other.position - boid.position).magnitude2() <= radius * radius
+            }
+        }
+    }
+
+    /// Speed the constant-speed models swim at within the kinematic
+    /// limits, `None` for Reynolds where the speed varies
+    pub fn constant_speed(&self) -> Option<f32> {
+        let speed = match self.model {
+            SimulationModel::Reynolds => return None,
+            SimulationModel::Vicsek => self.vicsek.speed,
+            SimulationModel::Couzin => self.couzin.speed,
+        };
+        Some(speed.max(self.limits.min_speed).min(self.limits.max_speed))
+    }
+
+    /// Advances every boid by one [`TIME_STEP`]
+    pub fn step<R: Rng + ?Sized>(&self, instances: &mut [Instance], rng: &mut R) {
+        let previous = instances.iter().map(|x| x.velocity).collect::<Vec<_>>();
+
+        match self.model {
+            SimulationModel::Reynolds => self.reynolds.step(instances, TIME_STEP),
+            SimulationModel::Vicsek => self.vicsek.step(instances, rng),
+            SimulationModel::Couzin => self.couzin.step(instances, rng, TIME_STEP),
+        }
+
+        for (boid, previous) in instances.iter_mut().zip(previous) {
+            let species = self.species.get(boid.species);
+            boid.velocity.y += self
+                .environment
+                .vertical_acceleration(species, boid.position.y)
+                * TIME_STEP;
+            if let Some(speed) = self.constant_speed() {
+                // The tank forces may only steer these boids, not speed them up
+                boid.velocity =
+                    normalize_or(boid.velocity, normalize_or(previous, Vector3::unit_x())) * speed;
+            }
+            boid.velocity = self.limits.apply(previous, boid.velocity, TIME_STEP);
+            boid.position += boid.velocity * TIME_STEP;
+            contain(boid);
+            boid.face_velocity();
+        }
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui) {
+        ComboBox::from_label("Model")
+            .selected_text(format!("{:?}", self.model))
+            .show_ui(ui, |ui| {
+                for model in SimulationModel::ALL {
+                    ui.selectable_value(&mut self.model, model, format!("{:?}", model));
+                }
+            });
+        ui.separator();
+
+        match self.model {
+            SimulationModel::Reynolds => self.reynolds.ui(ui),
+            SimulationModel::Vicsek => self.vicsek.ui(ui),
+            SimulationModel::Couzin => self.couzin.ui(ui),
+        }
+
+        ui.separator();
+        ui.label("Kinematic limits");
+        self.limits.ui(ui);
+
+        ui.separator();
+        ui.label("Floor and surface");
+        self.environment.ui(ui);
+
+        for (i, species) in self.species.iter_mut().enumerate() {
+            ui.collapsing(format!("Species {}", i), |ui| species.ui(ui));
+        }
+    }
+}
+
+/// Steers boids back towards the centre as they approach the side glass.
+/// The floor and surface are handled by [`Environment`].
+fn wall_avoidance(position: Vector3<f32>) -> Vector3<f32> {
+    let limit = AQUARIUM_RADIUS - WALL_MARGIN;
+    let push = |x: f32| {
+        if x > limit {
+            -(x - limit)
+        } else if x < -limit {
+            -limit - x
+        } else {
+            0.0
+        }
+    };
+    Vector3::new(push(position.x), 0.0, push(position.z)) * 4.0
+}
+
+/// Keeps boids inside the tank by reflecting them off the glass
+fn contain(boid: &mut Instance) {
+    let lower = [-AQUARIUM_RADIUS, AQUARIUM_FLOOR, -AQUARIUM_RADIUS];
+    let upper = [AQUARIUM_RADIUS, AQUARIUM_SURFACE, AQUARIUM_RADIUS];
+    for axis in 0..3 {
+        if boid.position[axis] > upper[axis] - 1.0 {
+            boid.position[axis] = upper[axis] - 1.0;
+            boid.velocity[axis] = -boid.velocity[axis].abs();
+        } else if boid.position[axis] < lower[axis] + 1.0 {
+            boid.position[axis] = lower[axis] + 1.0;
+            boid.velocity[axis] = boid.velocity[axis].abs();
+        }
+    }
+}
+
+fn normalize_or(v: Vector3<f32>, fallback: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        fallback
+    }
+}
+
+/// Rotates the unit vector `from` towards `to` by at most `max_angle` radians
+pub(crate) fn turn_towards(from: Vector3<f32>, to: Vector3<f32>, max_angle: f32) -> Vector3<f32> {
+    let angle = from.dot(to).clamp(-1.0, 1.0).acos();
+    if angle <= max_angle {
+        return to;
+    }
+    let mut axis = from.cross(to);
+    if axis.magnitude2() < 1e-12 {
+        // Pointing in opposite directions, any perpendicular axis will do
+        axis = from.cross(Vector3::unit_x());
+        if axis.magnitude2() < 1e-12 {
+            axis = from.cross(Vector3::unit_y());
+        }
+    }
+    let axis = axis.normalize();
+    from * max_angle.cos() + axis.cross(from) * max_angle.sin()
+}
+
+pub(crate) fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Vector3<f32> {
+    perturb(Vector3::unit_z(), PI, rng)
+}
+
+/// Uniformly random direction within a cone of half angle `angle` around `axis`
+pub(crate) fn perturb<R: Rng + ?Sized>(
+    axis: Vector3<f32>,
+    angle: f32,
+    rng: &mut R,
+) -> Vector3<f32> {
+    if angle.is_nan() || angle <= 0.0 {
+        return axis;
+    }
+    let cos_theta = rng.gen_range(angle.min(PI).cos()..=1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = rng.gen_range(0.0..2.0 * PI);
+
+    let helper = if axis.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let u = axis.cross(helper).normalize();
+    let v = axis.cross(u);
+
+    axis * cos_theta + (u * phi.cos() + v * phi.sin()) * sin_theta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    #[test]
+    fn turn_towards_is_limited_to_the_max_angle() {
+        let from = Vector3::unit_x();
+        let to = Vector3::unit_z();
+
+        let turned = turn_towards(from, to, 0.1);
+        assert!((turned.magnitude() - 1.0).abs() < EPSILON);
+        assert!((from.dot(turned).acos() - 0.1).abs() < EPSILON);
+        // Turned within the plane of the two headings
+        assert!(turned.y.abs() < EPSILON && turned.z > 0.0);
+
+        assert_eq!(turn_towards(from, to, PI), to);
+    }
+
+    #[test]
+    fn turn_towards_handles_opposite_headings() {
+        let from = Vector3::unit_x();
+        let turned = turn_towards(from, -from, 0.5);
+        assert!((turned.magnitude() - 1.0).abs() < EPSILON);
+        assert!((from.dot(turned).acos() - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn kinematics_clamp_speed() {
+        let limits = Kinematics {
+            max_acceleration: 1000.0,
+            max_turn_rate: 720.0,
+            ..Default::default()
+        };
+        let previous = Vector3::new(4.0, 0.0, 0.0);
+
+        let fast = limits.apply(previous, Vector3::new(20.0, 0.0, 0.0), TIME_STEP);
+        assert!((fast.magnitude() - limits.max_speed).abs() < EPSILON);
+
+        let slow = limits.apply(previous, Vector3::new(0.1, 0.0, 0.0), TIME_STEP);
+        assert!((slow.magnitude() - limits.min_speed).abs() < EPSILON);
+        assert!(slow.x > 0.0);
+
+        // A stopped boid keeps swimming the way it was going
+        let stopped = limits.apply(previous, Vector3::zero(), TIME_STEP);
+        assert!((stopped - Vector3::new(limits.min_speed, 0.0, 0.0)).magnitude() < EPSILON);
+    }
+
+    #[test]
+    fn kinematics_limit_acceleration_and_turning() {
+        let limits = Kinematics::default();
+        let previous = Vector3::new(4.0, 0.0, 0.0);
+
+        let pushed = limits.apply(previous, Vector3::new(6.0, 0.0, 0.0), TIME_STEP);
+        let max_change = limits.max_acceleration * TIME_STEP;
+        assert!(((pushed - previous).magnitude() - max_change).abs() < EPSILON);
+
+        let limits = Kinematics {
+            max_acceleration: 1000.0,
+            max_turn_rate: 90.0,
+            ..Default::default()
+        };
+        let turned = limits.apply(previous, Vector3::new(0.0, 0.0, 4.0), 1.0 / 3.0);
+        let angle = previous.normalize().dot(turned.normalize()).acos();
+        assert!((angle - 30f32.to_radians()).abs() < 1e-4);
+        assert!((turned.magnitude() - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn constant_speed_models_respect_kinematic_limits() {
+        let mut simulation = Simulation::new(SimulationModel::Vicsek);
+        simulation.vicsek.noise = 0.0;
+        simulation.vicsek.speed = 12.0;
+        simulation.limits.max_turn_rate = 90.0;
+        let speed = simulation.constant_speed().unwrap();
+        assert_eq!(speed, simulation.limits.max_speed);
+
+        let mut rng = rand::thread_rng();
+        let mut instances = vec![
+            Instance::new(
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(speed, 0.0, 0.0),
+                0,
+            ),
+            Instance::new(
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 0.0, speed),
+                0,
+            ),
+        ];
+        let before = instances.iter().map(|x| x.velocity).collect::<Vec<_>>();
+        simulation.step(&mut instances, &mut rng);
+
+        let max_turn = simulation.limits.max_turn_rate.to_radians() * TIME_STEP;
+        for (boid, before) in instances.iter().zip(before) {
+            let boid_speed = boid.velocity.magnitude();
+            assert!(boid_speed <= simulation.limits.max_speed + 1e-4);
+            assert!(boid_speed >= simulation.limits.min_speed - 1e-4);
+            let turned = before.normalize().dot(boid.velocity.normalize()).acos();
+            assert!(turned <= max_turn + 1e-3, "turned {} rad", turned);
+            assert!(
+                (boid.velocity - before).magnitude()
+                    <= simulation.limits.max_acceleration * TIME_STEP + 1e-4
+            );
+        }
+    }
+
+    #[test]
+    fn couzin_zones_and_blind_angle() {
+        let couzin = Couzin::default();
+        let boid = Instance::new(Vector3::zero(), Vector3::unit_x(), 0);
+        let at = |x: f32, z: f32| Instance::new(Vector3::new(x, 0.0, z), Vector3::unit_x(), 0);
+
+        assert_eq!(couzin.zone(&boid, &at(0.5, 0.0)), Some(Zone::Repulsion));
+        assert_eq!(couzin.zone(&boid, &at(2.0, 0.0)), Some(Zone::Orientation));
+        assert_eq!(couzin.zone(&boid, &at(0.0, 5.0)), Some(Zone::Attraction));
+        assert_eq!(couzin.zone(&boid, &at(couzin.radius() + 1.0, 0.0)), None);
+        // Straight behind is in the blind angle, however close
+        assert_eq!(couzin.zone(&boid, &at(-0.5, 0.0)), None);
+
+        let simulation = Simulation {
+            model: SimulationModel::Couzin,
+            ..Simulation::default()
+        };
+        assert!(simulation.perceives(&boid, &at(2.0, 0.0)));
+        assert!(!simulation.perceives(&boid, &at(-2.0, 0.0)));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::bind_group::{create_bind_group, CompactBindGroupDescriptor, CompactBindGroupEntry};
+use crate::camera::Camera;
+use crate::resources::load_binary;
+use crate::texture::Texture;
+use anyhow::Context;
+use cgmath::SquareMatrix;
+use egui::{ComboBox, Ui};
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+use wgpu::{BindGroupLayout, Device, Queue, RenderPass, TextureFormat};
+
+/// Bundled cube maps the tank can be shown against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Sky {
+    /// Plain black
+    None,
+    /// Fields at dusk, from cubemap.jpg
+    Sunset,
+    /// Clouds over a city, from cubemap2.jpg
+    Clouds,
+    /// Open sea with islands, from cubemaps_skybox.png
+    #[default]
+    Sea,
+}
+
+impl Sky {
+    pub const ALL: [Sky; 4] = [Sky::None, Sky::Sunset, Sky::Clouds, Sky::Sea];
+
+    /// Cross layout image in res/ holding the cube map
+    fn file_name(&self) -> Option<&'static str> {
+        match self {
+            Sky::None => None,
+            Sky::Sunset => Some("cubemap.jpg"),
+            Sky::Clouds => Some("cubemap2.jpg"),
+            Sky::Sea => Some("cubemaps_skybox.png"),
+        }
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui) {
+        ComboBox::from_label("Sky")
+            .selected_text(format!("{:?}", self))
+            .show_ui(ui, |ui| {
+                for sky in Sky::ALL {
+                    ui.selectable_value(self, sky, format!("{:?}", sky));
+                }
+            });
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+impl SkyUniform {
+    fn new(camera: &Camera) -> Self {
+        let view_proj = camera.build_rotation_projection_matrix();
+        Self {
+            inv_view_proj: view_proj
+                .invert()
+                .unwrap_or(cgmath::Matrix4::identity())
+                .into(),
+        }
+    }
+}
+
+/// Draws the selected cube map behind everything else. All the bundled
+/// cube maps are loaded up front, so switching between them is instant.
+pub(crate) struct Skybox {
+    pub(crate) sky: Sky,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    cube_maps: Vec<(Sky, wgpu::BindGroup)>,
+    layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub(crate) async fn new(
+        device: &Device,
+        queue: &Queue,
+        sky: Sky,
+        camera: &Camera,
+        format: TextureFormat,
+        sample_count: u32,
+    ) -> anyhow::Result<Self> {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("sky_buffer"),
+            contents: bytemuck::cast_slice(&[SkyUniform::new(camera)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let (uniform_bind_group, uniform_bind_group_layout) = create_bind_group(
+            device,
+            CompactBindGroupDescriptor {
+                label: Some("sky_bind_group"),
+                entries: &[CompactBindGroupEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    resource: uniform_buffer.as_entire_binding(),
+                    count: None,
+                }],
+            },
+        );
+
+        let cube_map_layout = cube_map_bind_group_layout(device);
+        let mut cube_maps = Vec::new();
+        for sky in Sky::ALL {
+            let Some(file_name) = sky.file_name() else {
+                continue;
+            };
+            let bytes = load_binary(file_name)
+                .await
+                .with_context(|| format!("Loading {}", file_name))?;
+            let texture = Texture::cube_from_cross(device, queue, &bytes, file_name)?;
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &cube_map_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(texture.sampler()),
+                    },
+                ],
+                label: Some(file_name),
+            });
+            cube_maps.push((sky, bind_group));
+        }
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("sky_pipeline_layout"),
+            bind_group_layouts: &[&uniform_bind_group_layout, &cube_map_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &layout, format, sample_count);
+
+        Ok(Self {
+            sky,
+            uniform_buffer,
+            uniform_bind_group,
+            cube_maps,
+            layout,
+            pipeline,
+        })
+    }
+
+    /// Rebuilds the pipeline for a new number of samples per pixel
+    pub(crate) fn set_msaa_samples(
+        &mut self,
+        device: &Device,
+        format: TextureFormat,
+        samples: u32,
+    ) {
+        self.pipeline = create_pipeline(device, &self.layout, format, samples);
+    }
+
+    /// Turns the sky with the camera as it is now
+    pub(crate) fn update(&self, queue: &Queue, camera: &Camera) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SkyUniform::new(camera)]),
+        );
+    }
+
+    /// Draws the sky, if any, without touching the depth buffer
+    pub(crate) fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        let Some((_, cube_map)) = self.cube_maps.iter().find(|(sky, _)| *sky == self.sky) else {
+            return;
+        };
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        render_pass.set_bind_group(1, cube_map, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn cube_map_bind_group_layout(device: &Device) -> BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("cube_map_bind_group_layout"),
+    })
+}
+
+fn create_pipeline(
+    device: &Device,
+    layout: &wgpu::PipelineLayout,
+    format: TextureFormat,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/skybox.wgsl"));
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("sky_pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Drawn first and never written to the depth buffer, so everything
+        // else ends up in front of it
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,273 @@
+use crate::flock::Flock;
+use crate::instance::Instance;
+use crate::simulation::Simulation;
+use crate::spawn::MAX_BOIDS;
+use anyhow::{bail, Context};
+use cgmath::Vector3;
+use egui::{Color32, Ui};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Bumped whenever the snapshot layout changes incompatibly
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct BoidState {
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    pub species: usize,
+    pub tint: [f32; 3],
+}
+
+/// Everything needed to put the tank back exactly as it was
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub step: u64,
+    pub boids: Vec<BoidState>,
+    pub simulation: Simulation,
+    pub rng: ChaCha12Rng,
+}
+
+/// Just the version, read first so that old files fail with a clear error
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+impl Snapshot {
+    pub fn capture(flock: &Flock) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            step: flock.steps,
+            boids: flock
+                .instances
+                .iter()
+                .zip(&flock.tints)
+                .map(|(instance, tint)| BoidState {
+                    position: instance.position.into(),
+                    velocity: instance.velocity.into(),
+                    species: instance.species,
+                    tint: [tint[0], tint[1], tint[2]],
+                })
+                .collect(),
+            simulation: flock.simulation.clone(),
+            rng: flock.rng.clone(),
+        }
+    }
+
+    /// Replaces the state of `flock` with this snapshot, stopping any
+    /// replay and starting the history over
+    pub fn restore(self, flock: &mut Flock) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (1..=MAX_BOIDS).contains(&self.boids.len()),
+            "Snapshot has {} boids, but needs 1 to {}",
+            self.boids.len(),
+            MAX_BOIDS
+        );
+        let finite = |state: &BoidState| {
+            let values = state
+                .position
+                .iter()
+                .chain(&state.velocity)
+                .chain(&state.tint);
+            values.into_iter().all(|x| x.is_finite())
+        };
+        if let Some(i) = self.boids.iter().position(|x| !finite(x)) {
+            bail!("Boid {} of the snapshot isn't a finite number", i);
+        }
+
+        let last_species = self.simulation.species.len().saturating_sub(1);
+        flock.instances = self
+            .boids
+            .iter()
+            .map(|state| {
+                Instance::new(
+                    Vector3::from(state.position),
+                    Vector3::from(state.velocity),
+                    state.species.min(last_species),
+                )
+            })
+            .collect();
+        flock.tints = self
+            .boids
+            .iter()
+            .map(|state| [state.tint[0], state.tint[1], state.tint[2], 0.0])
+            .collect();
+        flock.simulation = self.simulation;
+        flock.rng = self.rng;
+        flock.restart(self.step);
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+
+        let Versioned { version } = serde_json::from_str(&text)?;
+        if version != SNAPSHOT_VERSION {
+            bail!(
+                "Snapshot version {} is not supported (expected {})",
+                version,
+                SNAPSHOT_VERSION
+            );
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+/// Save and load buttons for the snapshot window
+pub(crate) struct SnapshotPanel {
+    path: String,
+    status: Option<anyhow::Result<String>>,
+}
+
+impl SnapshotPanel {
+    pub(crate) fn new() -> Self {
+        Self {
+            path: "snapshot.json".to_string(),
+            status: None,
+        }
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui, flock: &mut Flock) {
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut self.path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                self.status = Some(
+                    Snapshot::capture(flock)
+                        .save(&self.path)
+                        .map(|_| format!("Saved step {}", flock.steps)),
+                );
+            }
+            if ui.button("Load").clicked() {
+                self.status = Some(
+                    Snapshot::load(&self.path)
+                        .and_then(|snapshot| snapshot.restore(flock))
+                        .map(|_| format!("Loaded step {}", flock.steps)),
+                );
+            }
+        });
+
+        match &self.status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(e)) => {
+                ui.colored_label(Color32::LIGHT_RED, format!("{:#}", e));
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()))
+    }
+
+    /// Saves and loads a snapshot of `flock` through a file
+    fn save_and_load(flock: &Flock, name: &str) -> Snapshot {
+        let path = temp_path(name);
+        Snapshot::capture(flock).save(&path).unwrap();
+        let loaded = Snapshot::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap()
+    }
+
+    fn assert_same_boids(a: &Flock, b: &Flock) {
+        assert_eq!(a.instances.len(), b.instances.len());
+        for (a, b) in a.instances.iter().zip(&b.instances) {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.velocity, b.velocity);
+            assert_eq!(a.species, b.species);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut flock = Flock::new();
+        for _ in 0..10 {
+            flock.step();
+        }
+        let loaded = save_and_load(&flock, "snapshot-round-trip");
+        assert_eq!(loaded.version, SNAPSHOT_VERSION);
+
+        let mut restored = Flock::new();
+        restored
+            .simulation
+            .species
+            .push(restored.simulation.species[0].clone());
+        loaded.restore(&mut restored).unwrap();
+        assert_eq!(restored.steps, 10);
+        assert_same_boids(&flock, &restored);
+        assert_eq!(restored.tints, flock.tints);
+        assert_eq!(
+            restored.simulation.species.len(),
+            flock.simulation.species.len()
+        );
+    }
+
+    #[test]
+    fn restored_flocks_carry_on_the_same() {
+        // Vicsek noise draws from the saved random numbers every step
+        let mut flock = Flock::new();
+        flock.simulation.model = crate::simulation::SimulationModel::Vicsek;
+        for _ in 0..5 {
+            flock.step();
+        }
+        let mut restored = Flock::new();
+        save_and_load(&flock, "snapshot-determinism")
+            .restore(&mut restored)
+            .unwrap();
+
+        for _ in 0..30 {
+            flock.step();
+            restored.step();
+        }
+        assert_eq!(restored.steps, flock.steps);
+        assert_same_boids(&flock, &restored);
+    }
+
+    #[test]
+    fn rejects_broken_boids() {
+        let mut snapshot = Snapshot::capture(&Flock::new());
+        snapshot.boids[3].velocity[1] = f32::NAN;
+        let error = snapshot.restore(&mut Flock::new()).unwrap_err();
+        assert!(error.to_string().contains("Boid 3"), "{}", error);
+
+        let mut snapshot = Snapshot::capture(&Flock::new());
+        snapshot.boids.clear();
+        assert!(snapshot.restore(&mut Flock::new()).is_err());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut snapshot = Snapshot::capture(&Flock::new());
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        let path = temp_path("snapshot-version");
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = loaded.err().expect("a newer snapshot loaded").to_string();
+        assert!(error.contains("not supported"), "{}", error);
+    }
+}
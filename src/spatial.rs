@@ -0,0 +1,88 @@
+use cgmath::{InnerSpace, Vector3};
+use std::collections::HashMap;
+
+/// Uniform grid over boid positions, used for radius neighbour queries.
+///
+/// Cells are as wide as the query radius the grid was built for, so a query
+/// only has to look at the 27 cells around the point.
+pub(crate) struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<[i32; 3], Vec<usize>>,
+    positions: Vec<Vector3<f32>>,
+}
+
+impl SpatialGrid {
+    pub(crate) fn new(cell_size: f32, positions: impl IntoIterator<Item = Vector3<f32>>) -> Self {
+        let cell_size = cell_size.max(f32::EPSILON);
+        let positions = positions.into_iter().collect::<Vec<_>>();
+        let mut cells: HashMap<[i32; 3], Vec<usize>> = HashMap::new();
+        for (i, p) in positions.iter().enumerate() {
+            cells.entry(cell_of(*p, cell_size)).or_default().push(i);
+        }
+
+        Self {
+            cell_size,
+            cells,
+            positions,
+        }
+    }
+
+    /// Calls `f` with the index of every position within `radius` of `point`
+    /// (including the point itself if it is in the grid).
+    pub(crate) fn for_each_within(
+        &self,
+        point: Vector3<f32>,
+        radius: f32,
+        mut f: impl FnMut(usize),
+    ) {
+        let reach = (radius / self.cell_size).ceil() as i32;
+        let [cx, cy, cz] = cell_of(point, self.cell_size);
+        let radius2 = radius * radius;
+
+        for x in cx - reach..=cx + reach {
+            for y in cy - reach..=cy + reach {
+                for z in cz - reach..=cz + reach {
+                    let Some(cell) = self.cells.get(&[x, y, z]) else {
+                        continue;
+                    };
+                    for &i in cell {
+                        if (self.positions[i] - point).magnitude2() <= radius2 {
+                            f(i)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Distance from the position at `index` to the closest other one, or
+    /// `None` when there is nothing else within `max_radius`. The search
+    /// starts one cell out and doubles until it finds a neighbour.
+    pub(crate) fn nearest_distance(&self, index: usize, max_radius: f32) -> Option<f32> {
+        let point = self.positions[index];
+        let mut radius = self.cell_size;
+        loop {
+            let mut nearest2 = f32::INFINITY;
+            self.for_each_within(point, radius, |j| {
+                if j != index {
+                    nearest2 = nearest2.min((self.positions[j] - point).magnitude2());
+                }
+            });
+            if nearest2.is_finite() {
+                return Some(nearest2.sqrt());
+            }
+            if radius >= max_radius {
+                return None;
+            }
+            radius = (radius * 2.0).min(max_radius);
+        }
+    }
+}
+
+fn cell_of(p: Vector3<f32>, cell_size: f32) -> [i32; 3] {
+    [
+        (p.x / cell_size).floor() as i32,
+        (p.y / cell_size).floor() as i32,
+        (p.z / cell_size).floor() as i32,
+    ]
+}
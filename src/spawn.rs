@@ -0,0 +1,430 @@
+use crate::boids::{AQUARIUM_RADIUS, NUM_INSTANCES};
+use crate::flock::Flock;
+use crate::recording::{Recording, BINARY_MAGIC};
+use crate::simulation::random_unit_vector;
+use anyhow::Context;
+use cgmath::{InnerSpace, Vector3, Zero};
+use egui::{Color32, ComboBox, DragValue, Slider, Ui};
+use rand::Rng;
+use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+use std::path::Path;
+
+/// Most fish the tank is spawned or loaded with
+pub const MAX_BOIDS: usize = 10_000;
+
+/// Starting state of one fish read from an initial conditions file
+#[derive(Debug, Clone, Deserialize)]
+pub struct InitialBoid {
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    #[serde(default)]
+    pub species: usize,
+    /// Random when not given
+    #[serde(default)]
+    pub tint: Option<[f32; 3]>,
+}
+
+impl InitialBoid {
+    pub fn position(&self) -> Vector3<f32> {
+        Vector3::from(self.position)
+    }
+
+    pub fn velocity(&self) -> Vector3<f32> {
+        Vector3::from(self.velocity)
+    }
+
+    fn is_finite(&self) -> bool {
+        let tint = self.tint.iter().flatten();
+        self.position
+            .iter()
+            .chain(&self.velocity)
+            .chain(tint)
+            .all(|x| x.is_finite())
+    }
+}
+
+/// Where freshly spawned fish are placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SpawnShape {
+    /// Uniformly inside a cube
+    #[default]
+    Cube,
+    /// Uniformly inside a sphere
+    Sphere,
+    /// Gaussian blobs around random centres
+    Clusters,
+    /// On the surface of a sphere
+    Shell,
+    /// Inside a horizontal ring, ready to mill
+    Torus,
+    /// On a regular cubic grid
+    Lattice,
+}
+
+impl SpawnShape {
+    pub const ALL: [SpawnShape; 6] = [
+        SpawnShape::Cube,
+        SpawnShape::Sphere,
+        SpawnShape::Clusters,
+        SpawnShape::Shell,
+        SpawnShape::Torus,
+        SpawnShape::Lattice,
+    ];
+}
+
+/// Which way freshly spawned fish start swimming
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum VelocityMode {
+    /// Every fish in its own random direction
+    #[default]
+    Random,
+    /// Every fish in the same random direction
+    Aligned,
+    /// Circling the vertical axis through the centre of the tank
+    Rotational,
+}
+
+impl VelocityMode {
+    pub const ALL: [VelocityMode; 3] = [
+        VelocityMode::Random,
+        VelocityMode::Aligned,
+        VelocityMode::Rotational,
+    ];
+}
+
+/// Generates initial conditions from a shape and a velocity mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Spawner {
+    pub count: usize,
+    pub shape: SpawnShape,
+    pub velocity: VelocityMode,
+    /// Half the size of the spawn region, centred in the tank
+    pub radius: f32,
+    pub speed: f32,
+    /// Number of blobs for [`SpawnShape::Clusters`]
+    pub clusters: usize,
+    /// Standard deviation of each blob for [`SpawnShape::Clusters`]
+    pub cluster_spread: f32,
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Self {
+            count: NUM_INSTANCES,
+            shape: SpawnShape::default(),
+            velocity: VelocityMode::default(),
+            radius: AQUARIUM_RADIUS,
+            speed: 2.0,
+            clusters: 3,
+            cluster_spread: 2.0,
+        }
+    }
+}
+
+impl Spawner {
+    /// Spawns `count` fish, assigning the `species_count` species in turn
+    pub fn generate<R: Rng + ?Sized>(&self, species_count: usize, rng: &mut R) -> Vec<InitialBoid> {
+        let radius = self.radius.clamp(0.0, AQUARIUM_RADIUS);
+        let centres = (0..self.clusters.max(1))
+            .map(|_| cube_point(radius - self.cluster_spread.min(radius), rng))
+            .collect::<Vec<_>>();
+        let side = (self.count as f32).cbrt().ceil().max(1.0) as usize;
+        let heading = random_unit_vector(rng);
+
+        (0..self.count)
+            .map(|i| {
+                let position = match self.shape {
+                    SpawnShape::Cube => cube_point(radius, rng),
+                    SpawnShape::Sphere => {
+                        random_unit_vector(rng) * radius * rng.gen::<f32>().cbrt()
+                    }
+                    SpawnShape::Clusters => {
+                        let offset = Vector3::new(
+                            rng.sample(StandardNormal),
+                            rng.sample(StandardNormal),
+                            rng.sample(StandardNormal),
+                        );
+                        centres[i % centres.len()] + offset * self.cluster_spread
+                    }
+                    SpawnShape::Shell => random_unit_vector(rng) * radius,
+                    SpawnShape::Torus => {
+                        // Ring of major radius 0.6r and tube radius 0.25r
+                        let angle = rng.gen_range(0.0..TAU);
+                        let tube =
+                            random_unit_vector(rng) * 0.25 * radius * rng.gen::<f32>().cbrt();
+                        Vector3::new(angle.cos(), 0.0, angle.sin()) * 0.6 * radius + tube
+                    }
+                    SpawnShape::Lattice => {
+                        let spacing = 2.0 * radius / side as f32;
+                        let cell = Vector3::new(i % side, i / side % side, i / (side * side));
+                        cell.cast::<f32>().unwrap() * spacing
+                            - Vector3::new(1.0, 1.0, 1.0) * (radius - spacing / 2.0)
+                    }
+                };
+                let position = position.map(|x| x.clamp(-AQUARIUM_RADIUS, AQUARIUM_RADIUS));
+
+                let direction = match self.velocity {
+                    VelocityMode::Random => random_unit_vector(rng),
+                    VelocityMode::Aligned => heading,
+                    VelocityMode::Rotational => {
+                        let tangent = Vector3::unit_y().cross(position);
+                        if tangent.magnitude2() > 1e-6 {
+                            tangent.normalize()
+                        } else {
+                            random_unit_vector(rng)
+                        }
+                    }
+                };
+
+                InitialBoid {
+                    position: position.into(),
+                    velocity: (direction * self.speed).into(),
+                    species: i % species_count.max(1),
+                    tint: None,
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui) {
+        ui.add(
+            DragValue::new(&mut self.count)
+                .clamp_range(1..=MAX_BOIDS)
+                .prefix("Count: "),
+        );
+        ComboBox::from_label("Shape")
+            .selected_text(format!("{:?}", self.shape))
+            .show_ui(ui, |ui| {
+                for shape in SpawnShape::ALL {
+                    ui.selectable_value(&mut self.shape, shape, format!("{:?}", shape));
+                }
+            });
+        ComboBox::from_label("Velocity")
+            .selected_text(format!("{:?}", self.velocity))
+            .show_ui(ui, |ui| {
+                for mode in VelocityMode::ALL {
+                    ui.selectable_value(&mut self.velocity, mode, format!("{:?}", mode));
+                }
+            });
+        ui.add(Slider::new(&mut self.radius, 1.0..=AQUARIUM_RADIUS).text("Radius"));
+        ui.add(Slider::new(&mut self.speed, 0.0..=10.0).text("Speed"));
+        if self.shape == SpawnShape::Clusters {
+            ui.add(Slider::new(&mut self.clusters, 1..=10).text("Clusters"));
+            ui.add(Slider::new(&mut self.cluster_spread, 0.1..=10.0).text("Spread"));
+        }
+    }
+}
+
+fn cube_point<R: Rng + ?Sized>(radius: f32, rng: &mut R) -> Vector3<f32> {
+    if radius <= 0.0 {
+        return Vector3::zero();
+    }
+    Vector3::new(
+        rng.gen_range(-radius..radius),
+        rng.gen_range(-radius..radius),
+        rng.gen_range(-radius..radius),
+    )
+}
+
+/// JSON initial conditions are either a bare list of boids or a snapshot,
+/// whose boids carry the same fields
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InitialFile {
+    Boids(Vec<InitialBoid>),
+    Snapshot { boids: Vec<InitialBoid> },
+}
+
+/// Reads initial conditions from `path`.
+///
+/// JSON files hold a list of boids or a saved snapshot. CSV files need
+/// `x,y,z,vx,vy,vz` columns and may add `species` and a tint as `r,g,b`.
+/// Recordings in either format start from their last recorded step.
+/// Positions outside the tank are moved onto its walls.
+pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Vec<InitialBoid>> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+
+    let mut boids = if path.extension().is_some_and(|x| x == "json") {
+        match serde_json::from_slice(&bytes)
+            .with_context(|| format!("Parsing {}", path.display()))?
+        {
+            InitialFile::Boids(boids) | InitialFile::Snapshot { boids } => boids,
+        }
+    } else if bytes.starts_with(BINARY_MAGIC) || is_recording_csv(&bytes) {
+        let recording = Recording::load(path)?;
+        let last = recording.frames.last().unwrap();
+        last.boids
+            .iter()
+            .map(|boid| InitialBoid {
+                position: boid.position.into(),
+                velocity: boid.velocity.into(),
+                species: boid.species,
+                tint: None,
+            })
+            .collect()
+    } else {
+        from_csv(std::str::from_utf8(&bytes)?)
+            .with_context(|| format!("Parsing {}", path.display()))?
+    };
+
+    anyhow::ensure!(!boids.is_empty(), "{} has no boids", path.display());
+    anyhow::ensure!(
+        boids.len() <= MAX_BOIDS,
+        "{} has {} boids, more than the {} the tank holds",
+        path.display(),
+        boids.len(),
+        MAX_BOIDS
+    );
+    if let Some(i) = boids.iter().position(|x| !x.is_finite()) {
+        anyhow::bail!("Boid {} in {} isn't a finite number", i, path.display());
+    }
+    for boid in &mut boids {
+        boid.position = boid
+            .position
+            .map(|x| x.clamp(-AQUARIUM_RADIUS, AQUARIUM_RADIUS));
+    }
+    Ok(boids)
+}
+
+fn is_recording_csv(bytes: &[u8]) -> bool {
+    let header = bytes.split(|&x| x == b'\n').next().unwrap_or_default();
+    String::from_utf8_lossy(header)
+        .split(',')
+        .any(|x| x.trim() == "step")
+}
+
+fn from_csv(text: &str) -> anyhow::Result<Vec<InitialBoid>> {
+    let mut lines = text.lines().filter(|x| !x.trim().is_empty());
+    let header = lines.next().context("Missing CSV header")?;
+    let columns = header.split(',').map(str::trim).collect::<Vec<_>>();
+    let column = |name: &str| columns.iter().position(|x| *x == name);
+    let require = |name: &str| column(name).with_context(|| format!("Missing column '{}'", name));
+
+    let position = [require("x")?, require("y")?, require("z")?];
+    let velocity = [require("vx")?, require("vy")?, require("vz")?];
+    let species = column("species");
+    let tint = match (column("r"), column("g"), column("b")) {
+        (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+        _ => None,
+    };
+
+    lines
+        .enumerate()
+        .map(|(row, line)| {
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            let field = |i: usize| {
+                fields
+                    .get(i)
+                    .with_context(|| format!("Row {} is missing a value", row + 2))
+            };
+            let vector = |[x, y, z]: [usize; 3]| -> anyhow::Result<[f32; 3]> {
+                Ok([field(x)?.parse()?, field(y)?.parse()?, field(z)?.parse()?])
+            };
+
+            let boid = InitialBoid {
+                position: vector(position)?,
+                velocity: vector(velocity)?,
+                species: match species {
+                    Some(i) => field(i)?.parse()?,
+                    None => 0,
+                },
+                tint: tint.map(vector).transpose()?,
+            };
+            anyhow::ensure!(
+                boid.is_finite(),
+                "Row {} has a value that isn't a finite number",
+                row + 2
+            );
+            Ok(boid)
+        })
+        .collect()
+}
+
+/// Controls for starting the flock over from a spawner or a file
+pub(crate) struct SpawnPanel {
+    path: String,
+    status: Option<anyhow::Result<String>>,
+}
+
+impl SpawnPanel {
+    pub(crate) fn new() -> Self {
+        Self {
+            path: "initial.csv".to_string(),
+            status: None,
+        }
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui, flock: &mut Flock) {
+        flock.spawner.ui(ui);
+        if ui.button("Spawn").clicked() {
+            let initial = flock
+                .spawner
+                .generate(flock.simulation.species.len(), &mut flock.rng);
+            flock.respawn(&initial);
+            self.status = Some(Ok(format!("Spawned {} boids", initial.len())));
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut self.path);
+        });
+        if ui.button("Load initial conditions").clicked() {
+            self.status = Some(load(&self.path).map(|initial| {
+                flock.respawn(&initial);
+                format!("Spawned {} boids", initial.len())
+            }));
+        }
+
+        match &self.status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(e)) => {
+                ui.colored_label(Color32::LIGHT_RED, format!("{:#}", e));
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads `text` as an initial conditions CSV file
+    fn load_csv(name: &str, text: &str) -> anyhow::Result<Vec<InitialBoid>> {
+        let path = std::env::temp_dir().join(format!("{}-{}.csv", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let boids = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        boids
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_finite() {
+        let text = "x,y,z,vx,vy,vz\n0,0,0,1,0,0\n0,NaN,0,1,0,0\n";
+        let error = format!("{:#}", load_csv("spawn-nan", text).unwrap_err());
+        assert!(error.contains("Row 3"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_too_many_boids() {
+        let mut text = "x,y,z,vx,vy,vz\n".to_string();
+        for _ in 0..=MAX_BOIDS {
+            text.push_str("0,0,0,1,0,0\n");
+        }
+        assert!(load_csv("spawn-count", &text).is_err());
+    }
+
+    #[test]
+    fn moves_boids_into_the_tank() {
+        let text = "x,y,z,vx,vy,vz\n1000,-1000,3,1,0,0\n";
+        let boids = load_csv("spawn-clamp", text).unwrap();
+        assert_eq!(boids[0].position, [AQUARIUM_RADIUS, -AQUARIUM_RADIUS, 3.0]);
+    }
+}
@@ -0,0 +1,436 @@
+use crate::boids::{AQUARIUM_FLOOR, AQUARIUM_RADIUS, AQUARIUM_SURFACE};
+use crate::camera::Camera;
+use crate::flock::Flock;
+use crate::tint::Tinting;
+use anyhow::Context;
+use cgmath::{InnerSpace, Matrix4, Vector2, Vector3};
+use egui::{Color32, ComboBox, Slider, Ui};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Length of a fish glyph in world units
+const FISH_LENGTH: f32 = 1.5;
+/// Width of a fish glyph as a fraction of its length
+const FISH_WIDTH: f32 = 0.4;
+/// Seconds of travel a velocity arrow covers
+const ARROW_SECONDS: f32 = 0.5;
+/// Room left around the tank in axis views, as a fraction of its size
+const MARGIN: f32 = 1.05;
+const EDGE_COLOR: &str = "#8c8c8c";
+const OUTLINE_COLOR: &str = "#333333";
+
+/// Which way a figure looks at the tank
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(clap::ValueEnum))]
+pub enum Projection {
+    /// Through the camera, with perspective
+    #[default]
+    Camera,
+    /// Along the x axis, z to the right and y up
+    X,
+    /// Down the y axis from above, x to the right and z down
+    Y,
+    /// Along the z axis, x to the right and y up
+    Z,
+}
+
+impl Projection {
+    pub const ALL: [Projection; 4] = [
+        Projection::Camera,
+        Projection::X,
+        Projection::Y,
+        Projection::Z,
+    ];
+}
+
+/// What goes into a figure of the flock
+#[derive(Debug, Clone)]
+pub struct Figure {
+    pub projection: Projection,
+    /// Width in pixels, the height following from the projection
+    pub width: f32,
+    /// Draws every fish's velocity as an arrow
+    pub arrows: bool,
+    /// Seconds of history drawn behind every fish, none when 0
+    pub trail: f32,
+}
+
+impl Default for Figure {
+    fn default() -> Self {
+        Self {
+            projection: Projection::default(),
+            width: 800.0,
+            arrows: false,
+            trail: 0.0,
+        }
+    }
+}
+
+impl Figure {
+    /// Writes the flock as it is now as an SVG file, coloured by `tinting`
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+        flock: &Flock,
+        camera: &Camera,
+        tinting: &mut Tinting,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        self.write(&mut out, flock, camera, tinting)
+            .and_then(|_| out.flush())
+            .with_context(|| format!("Writing {}", path.display()))
+    }
+
+    pub fn write(
+        &self,
+        out: &mut impl Write,
+        flock: &Flock,
+        camera: &Camera,
+        tinting: &mut Tinting,
+    ) -> io::Result<()> {
+        let projector = Projector::new(self, camera);
+        let (width, height) = projector.size;
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.0} {h:.0}">"#,
+            w = width,
+            h = height
+        )?;
+        writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#)?;
+
+        writeln!(
+            out,
+            r#"<g fill="none" stroke="{}" stroke-width="1">"#,
+            EDGE_COLOR
+        )?;
+        for (a, b) in tank_edges() {
+            let (Some(a), Some(b)) = (projector.point(a), projector.point(b)) else {
+                continue;
+            };
+            // Axis views see edges along the axis end on
+            if (b - a).magnitude2() >= 1.0 {
+                writeln!(
+                    out,
+                    r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}"/>"#,
+                    a.x, a.y, b.x, b.y
+                )?;
+            }
+        }
+        writeln!(out, "</g>")?;
+
+        let colors = tinting
+            .colors(
+                &flock.instances,
+                &flock.tints,
+                &flock.schools,
+                &flock.simulation,
+            )
+            .into_iter()
+            .map(|[r, g, b, _]| hex([r, g, b]))
+            .collect::<Vec<_>>();
+
+        if self.trail > 0.0 {
+            // Frames from before the flock changed size can't be matched up
+            let frames = flock
+                .history
+                .recent(self.trail)
+                .filter(|frame| frame.len() == flock.instances.len())
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                r#"<g fill="none" stroke-width="1" stroke-opacity="0.5">"#
+            )?;
+            for (i, color) in colors.iter().enumerate() {
+                let points = frames
+                    .iter()
+                    .filter_map(|frame| projector.point(frame.positions[i]))
+                    .map(|p| format!("{:.1},{:.1}", p.x, p.y))
+                    .collect::<Vec<_>>();
+                if points.len() > 1 {
+                    writeln!(
+                        out,
+                        r#"<polyline points="{}" stroke="{}"/>"#,
+                        points.join(" "),
+                        color
+                    )?;
+                }
+            }
+            writeln!(out, "</g>")?;
+        }
+
+        // Painter's algorithm, the nearest fish drawn last
+        let mut order = (0..flock.instances.len())
+            .filter_map(|i| Some((i, projector.depth(flock.instances[i].position)?)))
+            .collect::<Vec<_>>();
+        order.sort_by(|a, b| b.1.total_cmp(&a.1));
+        writeln!(out, r#"<g stroke="{}" stroke-width="0.5">"#, OUTLINE_COLOR)?;
+        for (i, _) in order {
+            let fish = &flock.instances[i];
+            let color = &colors[i];
+            if self.arrows {
+                let tip = fish.position + fish.velocity * ARROW_SECONDS;
+                if let (Some(a), Some(b)) = (projector.point(fish.position), projector.point(tip)) {
+                    write_arrow(out, a, b, color)?;
+                }
+            }
+            write_fish(out, &projector, fish.position, fish.velocity, color)?;
+        }
+        writeln!(out, "</g>")?;
+        writeln!(out, "</svg>")
+    }
+}
+
+/// Maps world positions to pixels of the figure
+struct Projector {
+    projection: Projection,
+    size: (f32, f32),
+    view_projection: Matrix4<f32>,
+    /// Pixels per world unit at unit depth for the camera, and everywhere
+    /// for the axes
+    scale: f32,
+    forward: Vector3<f32>,
+}
+
+impl Projector {
+    fn new(figure: &Figure, camera: &Camera) -> Self {
+        let width = figure.width;
+        match figure.projection {
+            Projection::Camera => {
+                let height = width / camera.aspect;
+                let focal = 1.0 / (camera.fovy.to_radians() * 0.5).tan();
+                Self {
+                    projection: figure.projection,
+                    size: (width, height),
+                    view_projection: camera.build_view_projection_matrix(),
+                    scale: focal * height * 0.5,
+                    forward: (camera.target - camera.eye).normalize(),
+                }
+            }
+            projection => {
+                let forward = match projection {
+                    Projection::X => Vector3::unit_x(),
+                    Projection::Y => -Vector3::unit_y(),
+                    _ => -Vector3::unit_z(),
+                };
+                Self {
+                    projection,
+                    size: (width, width),
+                    view_projection: Matrix4::from_scale(1.0),
+                    scale: width * 0.5 / (AQUARIUM_RADIUS * MARGIN),
+                    forward,
+                }
+            }
+        }
+    }
+
+    /// Position in pixels from the top left, or `None` behind the camera
+    fn point(&self, p: Vector3<f32>) -> Option<Vector2<f32>> {
+        let (width, height) = self.size;
+        let (x, y) = match self.projection {
+            Projection::Camera => {
+                let clip = self.view_projection * p.extend(1.0);
+                if clip.w <= 0.0 {
+                    return None;
+                }
+                let ndc = clip.truncate() / clip.w;
+                return Some(Vector2::new(
+                    (ndc.x + 1.0) * 0.5 * width,
+                    (1.0 - ndc.y) * 0.5 * height,
+                ));
+            }
+            Projection::X => (p.z, -p.y),
+            Projection::Y => (p.x, p.z),
+            Projection::Z => (p.x, -p.y),
+        };
+        // Centred on the middle of the tank
+        let middle = (AQUARIUM_FLOOR + AQUARIUM_SURFACE) * 0.5;
+        let y = match self.projection {
+            Projection::Y => y,
+            _ => y + middle,
+        };
+        Some(Vector2::new(
+            width * 0.5 + x * self.scale,
+            height * 0.5 + y * self.scale,
+        ))
+    }
+
+    /// Distance along the view direction, or `None` behind the camera
+    fn depth(&self, p: Vector3<f32>) -> Option<f32> {
+        match self.projection {
+            Projection::Camera => {
+                let w = (self.view_projection * p.extend(1.0)).w;
+                (w > 0.0).then_some(w)
+            }
+            _ => Some(p.dot(self.forward)),
+        }
+    }
+
+    /// Pixels a world unit at `p` covers
+    fn pixels_per_unit(&self, p: Vector3<f32>) -> f32 {
+        match self.projection {
+            Projection::Camera => self.scale / self.depth(p).unwrap_or(1.0),
+            _ => self.scale,
+        }
+    }
+}
+
+/// The twelve edges of the tank, between corners differing in one
+/// coordinate
+fn tank_edges() -> Vec<(Vector3<f32>, Vector3<f32>)> {
+    let corner = |i: usize| {
+        let pick = |bit: usize, low: f32, high: f32| if i & bit == 0 { low } else { high };
+        Vector3::new(
+            pick(1, -AQUARIUM_RADIUS, AQUARIUM_RADIUS),
+            pick(2, AQUARIUM_FLOOR, AQUARIUM_SURFACE),
+            pick(4, -AQUARIUM_RADIUS, AQUARIUM_RADIUS),
+        )
+    };
+    (0..8)
+        .flat_map(|i| [1, 2, 4].map(|bit| (i, bit)))
+        .filter(|(i, bit)| i & bit == 0)
+        .map(|(i, bit)| (corner(i), corner(i | bit)))
+        .collect()
+}
+
+/// Draws a fish as a dart pointing the way it swims, foreshortened by the
+/// projection, or as a dot when it swims straight at the viewer
+fn write_fish(
+    out: &mut impl Write,
+    projector: &Projector,
+    position: Vector3<f32>,
+    velocity: Vector3<f32>,
+    color: &str,
+) -> io::Result<()> {
+    let width = FISH_LENGTH * FISH_WIDTH * projector.pixels_per_unit(position);
+    let direction = if velocity.magnitude2() > 0.0 {
+        velocity.normalize_to(FISH_LENGTH * 0.5)
+    } else {
+        Vector3::new(0.0, 0.0, 0.0)
+    };
+    let (Some(nose), Some(tail)) = (
+        projector.point(position + direction),
+        projector.point(position - direction),
+    ) else {
+        return Ok(());
+    };
+
+    let along = nose - tail;
+    if along.magnitude() < width * 0.5 {
+        let centre = (nose + tail) * 0.5;
+        return writeln!(
+            out,
+            r#"<circle cx="{:.1}" cy="{:.1}" r="{:.1}" fill="{}"/>"#,
+            centre.x,
+            centre.y,
+            width * 0.5,
+            color
+        );
+    }
+    let side = Vector2::new(-along.y, along.x).normalize_to(width * 0.5);
+    let notch = tail + along * 0.2;
+    let points = [nose, tail + side, notch, tail - side]
+        .map(|p| format!("{:.1},{:.1}", p.x, p.y))
+        .join(" ");
+    writeln!(out, r#"<polygon points="{}" fill="{}"/>"#, points, color)
+}
+
+/// Draws a line from `from` to `to` with an arrowhead at `to`
+fn write_arrow(
+    out: &mut impl Write,
+    from: Vector2<f32>,
+    to: Vector2<f32>,
+    color: &str,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        r#"<line x1="{:.1}" y1="{:.1}" x2="{:.1}" y2="{:.1}" stroke="{}" stroke-width="1"/>"#,
+        from.x, from.y, to.x, to.y, color
+    )?;
+    let along = to - from;
+    let length = along.magnitude();
+    if length < 1.0 {
+        return Ok(());
+    }
+    let head = (length * 0.3).min(6.0);
+    let back = to - along * (head / length);
+    let side = Vector2::new(-along.y, along.x) * (head * 0.5 / length);
+    let points = [to, back + side, back - side]
+        .map(|p| format!("{:.1},{:.1}", p.x, p.y))
+        .join(" ");
+    writeln!(
+        out,
+        r#"<polygon points="{}" fill="{}" stroke="none"/>"#,
+        points, color
+    )
+}
+
+fn hex([r, g, b]: [f32; 3]) -> String {
+    let [r, g, b] = [r, g, b].map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Options and a save button for the figure window
+pub(crate) struct FigurePanel {
+    figure: Figure,
+    path: String,
+    status: Option<anyhow::Result<String>>,
+}
+
+impl FigurePanel {
+    pub(crate) fn new() -> Self {
+        Self {
+            figure: Figure::default(),
+            path: "flock.svg".to_string(),
+            status: None,
+        }
+    }
+
+    pub(crate) fn ui(
+        &mut self,
+        ui: &mut Ui,
+        flock: &Flock,
+        camera: &Camera,
+        tinting: &mut Tinting,
+    ) {
+        let figure = &mut self.figure;
+        ComboBox::from_label("Projection")
+            .selected_text(format!("{:?}", figure.projection))
+            .show_ui(ui, |ui| {
+                for projection in Projection::ALL {
+                    ui.selectable_value(
+                        &mut figure.projection,
+                        projection,
+                        format!("{:?}", projection),
+                    );
+                }
+            });
+        ui.add(Slider::new(&mut figure.width, 200.0..=4000.0).text("Width (px)"));
+        ui.checkbox(&mut figure.arrows, "Velocity arrows");
+        ui.add(Slider::new(&mut figure.trail, 0.0..=flock.history.duration).text("Trail (s)"));
+
+        ui.horizontal(|ui| {
+            ui.label("File");
+            ui.text_edit_singleline(&mut self.path);
+        });
+        if ui.button("Save SVG").clicked() {
+            self.status = Some(
+                figure
+                    .save(&self.path, flock, camera, tinting)
+                    .map(|_| format!("Saved step {}", flock.steps)),
+            );
+        }
+
+        match &self.status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(e)) => {
+                ui.colored_label(Color32::LIGHT_RED, format!("{:#}", e));
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use crate::boids::{AQUARIUM_FLOOR, AQUARIUM_RADIUS, AQUARIUM_SURFACE};
+use crate::camera::Camera;
+use crate::flock::Flock;
+use crate::recording::{Recorder, RecordingFormat};
+use crate::scenario::Scenario;
+use crate::simulation::TIME_STEP;
+use crate::tint::{TintMode, Tinting};
+use crate::Options;
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4};
+use instant::{Duration, Instant};
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// Colour of the tank's edges
+const EDGE_COLOR: [f32; 3] = [0.3, 0.35, 0.45];
+/// Length of the line drawn behind every fish to show its heading
+const FISH_LENGTH: f32 = 1.5;
+/// Room left around the tank, as a fraction of its size
+const MARGIN: f32 = 1.05;
+
+/// Characters the tank is drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Glyphs {
+    /// Braille patterns, 2 by 4 dots per character in one colour
+    #[default]
+    Braille,
+    /// Half blocks, 1 by 2 dots per character in two colours
+    Blocks,
+}
+
+impl Glyphs {
+    /// Dots across and down each character
+    fn cell(&self) -> (usize, usize) {
+        match self {
+            Glyphs::Braille => (2, 4),
+            Glyphs::Blocks => (1, 2),
+        }
+    }
+}
+
+/// How [`run_terminal`] draws the tank
+#[derive(Debug, Clone)]
+pub struct Terminal {
+    /// Size of the view in characters, including the status line
+    pub columns: usize,
+    pub rows: usize,
+    pub glyphs: Glyphs,
+    /// What the colour of every fish shows
+    pub colour: TintMode,
+    /// Redraws per second of real time
+    pub fps: f32,
+}
+
+impl Default for Terminal {
+    /// Fills the terminal if the shell exports its size in `COLUMNS` and
+    /// `LINES`, and 80 by 24 characters otherwise
+    fn default() -> Self {
+        let size = |name, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            columns: size("COLUMNS", 80),
+            rows: size("LINES", 24),
+            glyphs: Glyphs::default(),
+            colour: TintMode::default(),
+            fps: 15.0,
+        }
+    }
+}
+
+/// Runs the simulation in real time and draws it as coloured text, looking
+/// along the scenario's camera without perspective. Needs no window or GPU,
+/// so it works over SSH. Runs until the limit in `options` is reached, or
+/// until interrupted if there is none.
+pub fn run_terminal(options: Options, terminal: Terminal) -> anyhow::Result<()> {
+    crate::init_logging();
+
+    anyhow::ensure!(terminal.fps > 0.0, "The refresh rate must be positive");
+    anyhow::ensure!(
+        terminal.columns > 0 && terminal.rows > 1,
+        "The terminal must be at least 1 by 2 characters"
+    );
+    let scenario = Scenario::open(options.scenario.as_deref(), &options.overrides)?;
+    let mut flock = Flock::new();
+    scenario.apply(&mut flock, None)?;
+    if let Some(path) = &options.record {
+        let format = RecordingFormat::from_path(path);
+        flock.recorder = Some(Recorder::create(path, format, flock.instances.len())?);
+    }
+    let mut tinting = Tinting::new();
+    tinting.mode = terminal.colour;
+
+    let (cell_width, cell_height) = terminal.glyphs.cell();
+    let mut canvas = Canvas::new(
+        terminal.columns * cell_width,
+        (terminal.rows - 1) * cell_height,
+    );
+    // Terminal characters are about twice as tall as wide, so every dot
+    // of either kind of glyph is about square
+    let aspect = canvas.width as f32 / canvas.height as f32;
+    let camera = scenario.camera.to_camera(aspect);
+
+    let mut stdout = std::io::stdout().lock();
+    write!(stdout, "\x1b[2J")?;
+    let interval = Duration::from_secs_f32(1.0 / terminal.fps);
+    let mut last = Instant::now();
+    let mut frames = 0;
+    while !options.limit.reached(flock.steps, frames) {
+        let now = Instant::now();
+        flock.advance((now - last).as_secs_f32());
+        last = now;
+        if let Some(e) = flock.recorder_error.take() {
+            return Err(e.context("Recording stopped"));
+        }
+
+        canvas.clear();
+        draw(&mut canvas, &camera, &flock, &mut tinting);
+        let mut text = String::from("\x1b[H");
+        match terminal.glyphs {
+            Glyphs::Braille => canvas.braille(&mut text),
+            Glyphs::Blocks => canvas.blocks(&mut text),
+        }
+        let order = &flock.order;
+        write!(
+            text,
+            "\x1b[0m\x1b[K{} fish  {:.1} s  polarization {:.2}  milling {:.2}  {:?}",
+            flock.instances.len(),
+            flock.steps as f32 * TIME_STEP,
+            order.polarization,
+            order.milling,
+            order.phase()
+        )?;
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()?;
+        frames += 1;
+
+        if let Some(rest) = interval.checked_sub(now.elapsed()) {
+            std::thread::sleep(rest);
+        }
+    }
+    writeln!(stdout, "\x1b[0m")?;
+    flock.stop_recording()
+}
+
+/// Draws the edges of the tank, then every fish as a short line pointing
+/// the way it swims, the nearest ones on top
+fn draw(canvas: &mut Canvas, camera: &Camera, flock: &Flock, tinting: &mut Tinting) {
+    let corner = |i: usize| {
+        let pick = |bit: usize, low: f32, high: f32| if i & bit == 0 { low } else { high };
+        Vector3::new(
+            pick(1, -AQUARIUM_RADIUS, AQUARIUM_RADIUS),
+            pick(2, AQUARIUM_FLOOR, AQUARIUM_SURFACE),
+            pick(4, -AQUARIUM_RADIUS, AQUARIUM_RADIUS),
+        )
+    };
+    // Zoom so the tank's corners just fit, whichever way the camera looks
+    let unit = camera.build_orthographic_matrix(1.0);
+    let half_height = (0..8)
+        .map(|i| {
+            let clip = unit * corner(i).extend(1.0);
+            clip.x.abs().max(clip.y.abs())
+        })
+        .fold(0.0, f32::max)
+        * MARGIN;
+    let view_projection = camera.build_orthographic_matrix(half_height);
+    let (width, height) = (canvas.width as f32, canvas.height as f32);
+    // Position of a point in dots from the top left, which may be off the
+    // canvas
+    let project = |point: Vector3<f32>| {
+        let clip: Vector4<f32> = view_projection * point.extend(1.0);
+        Vector2::new((clip.x + 1.0) * 0.5 * width, (1.0 - clip.y) * 0.5 * height)
+    };
+
+    // Corners differing in exactly one coordinate share an edge
+    for i in 0..8 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                let (a, b) = (project(corner(i)), project(corner(i | bit)));
+                canvas.line(a, b, EDGE_COLOR);
+            }
+        }
+    }
+
+    let colors = tinting.colors(
+        &flock.instances,
+        &flock.tints,
+        &flock.schools,
+        &flock.simulation,
+    );
+    let forward = (camera.target - camera.eye).normalize();
+    let mut order = (0..flock.instances.len()).collect::<Vec<_>>();
+    let depth = |i: usize| flock.instances[i].position.dot(forward);
+    order.sort_by(|&a, &b| depth(b).total_cmp(&depth(a)));
+    for i in order {
+        let fish = &flock.instances[i];
+        let [r, g, b, _] = colors[i];
+        let tail = if fish.velocity.magnitude2() > 0.0 {
+            fish.position - fish.velocity.normalize_to(FISH_LENGTH)
+        } else {
+            fish.position
+        };
+        canvas.line(project(tail), project(fish.position), [r, g, b]);
+    }
+}
+
+/// Grid of coloured dots, several of which make up each character
+struct Canvas {
+    width: usize,
+    height: usize,
+    dots: Vec<Option<[f32; 3]>>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            dots: vec![None; width * height],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.dots.fill(None);
+    }
+
+    fn plot(&mut self, x: f32, y: f32, color: [f32; 3]) {
+        if x >= 0.0 && y >= 0.0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.dots[y as usize * self.width + x as usize] = Some(color);
+        }
+    }
+
+    fn line(&mut self, a: Vector2<f32>, b: Vector2<f32>, color: [f32; 3]) {
+        let steps = (b - a).x.abs().max((b - a).y.abs()).ceil().max(1.0);
+        // Lines far off the canvas aren't worth walking along
+        if steps > 4.0 * (self.width + self.height) as f32 {
+            return;
+        }
+        for i in 0..=steps as usize {
+            let p = a + (b - a) * (i as f32 / steps);
+            self.plot(p.x, p.y, color);
+        }
+    }
+
+    /// Writes the canvas as braille patterns, each character coloured by
+    /// the average of its dots
+    fn braille(&self, text: &mut String) {
+        // Bit of every dot of a braille pattern, by column and then row
+        const BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        let mut pen = Pen::default();
+        for row in (0..self.height).step_by(4) {
+            for column in (0..self.width).step_by(2) {
+                let mut bits = 0;
+                let mut sum = [0.0; 3];
+                let mut count = 0.0;
+                for (dx, column_bits) in BITS.iter().enumerate() {
+                    for (dy, bit) in column_bits.iter().enumerate() {
+                        let dot = self.dot(column + dx, row + dy);
+                        if let Some(color) = dot {
+                            bits |= bit;
+                            (0..3).for_each(|i| sum[i] += color[i]);
+                            count += 1.0;
+                        }
+                    }
+                }
+                if bits == 0 {
+                    text.push(' ');
+                } else {
+                    pen.foreground(text, sum.map(|x| x / count));
+                    text.push(char::from_u32(0x2800 + bits).unwrap_or(' '));
+                }
+            }
+            pen.newline(text);
+        }
+    }
+
+    /// Writes the canvas as half blocks, the upper dot in the foreground
+    /// colour and the lower one in the background colour
+    fn blocks(&self, text: &mut String) {
+        let mut pen = Pen::default();
+        for row in (0..self.height).step_by(2) {
+            for column in 0..self.width {
+                match (self.dot(column, row), self.dot(column, row + 1)) {
+                    (None, None) => {
+                        pen.background(text, None);
+                        text.push(' ');
+                    }
+                    (Some(top), bottom) => {
+                        pen.foreground(text, top);
+                        pen.background(text, bottom);
+                        text.push('▀');
+                    }
+                    (None, Some(bottom)) => {
+                        pen.foreground(text, bottom);
+                        pen.background(text, None);
+                        text.push('▄');
+                    }
+                }
+            }
+            pen.newline(text);
+        }
+    }
+
+    fn dot(&self, x: usize, y: usize) -> Option<[f32; 3]> {
+        if x < self.width && y < self.height {
+            self.dots[y * self.width + x]
+        } else {
+            None
+        }
+    }
+}
+
+/// Tracks the colours the terminal is set to, to only send escape codes
+/// when they change
+#[derive(Default)]
+struct Pen {
+    foreground: Option<[u8; 3]>,
+    background: Option<[u8; 3]>,
+}
+
+impl Pen {
+    fn foreground(&mut self, text: &mut String, color: [f32; 3]) {
+        let color = to_rgb8(color);
+        if self.foreground != Some(color) {
+            let [r, g, b] = color;
+            write!(text, "\x1b[38;2;{};{};{}m", r, g, b).unwrap();
+            self.foreground = Some(color);
+        }
+    }
+
+    fn background(&mut self, text: &mut String, color: Option<[f32; 3]>) {
+        let color = color.map(to_rgb8);
+        if self.background != color {
+            match color {
+                Some([r, g, b]) => write!(text, "\x1b[48;2;{};{};{}m", r, g, b).unwrap(),
+                None => text.push_str("\x1b[49m"),
+            }
+            self.background = color;
+        }
+    }
+
+    /// Ends the line, clearing whatever the last frame left after it
+    fn newline(&mut self, text: &mut String) {
+        text.push_str("\x1b[0m\x1b[K\r\n");
+        *self = Pen::default();
+    }
+}
+
+fn to_rgb8(color: [f32; 3]) -> [u8; 3] {
+    color.map(|x| (x.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
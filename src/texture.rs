@@ -88,16 +88,109 @@ impl Texture {
         })
     }
 
+    /// Cube map from a single image of its faces laid out as a cross,
+    /// either 4 faces wide and 3 high or 3 wide and 4 high. The horizontal
+    /// cross has +Y on top of and -Y below the second face of the middle
+    /// row, which holds -X, +Z, +X, -Z. The vertical cross has +Y, +Z, -Y
+    /// and -Z down its middle column, with -X and +X either side of +Z.
+    pub fn cube_from_cross(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        let faces = cross_faces(&img, label)?;
+        Self::cube_from_faces(device, queue, &faces, label)
+    }
+
+    /// Cube map from six square images of the same size, in the order
+    /// +X, -X, +Y, -Y, +Z, -Z
+    pub fn cube_from_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: &str,
+    ) -> Result<Self> {
+        let (width, height) = faces[0].dimensions();
+        ensure!(
+            width == height && faces.iter().all(|x| x.dimensions() == (width, height)),
+            "The faces of {} must be squares of the same size",
+            label
+        );
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 6,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (layer, face) in faces.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                },
+                &face.to_rgba8(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler: Some(sampler),
+        })
+    }
+
     pub fn create_depth_texture(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        (width, height): (u32, u32),
         label: &str,
         sample_count: u32,
     ) -> Self {
         let size = wgpu::Extent3d {
             // 2.
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -138,13 +231,14 @@ impl Texture {
     // Create multi sampled framebuffer
     pub fn create_msfb_texture(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        (width, height): (u32, u32),
+        format: wgpu::TextureFormat,
         label: &str,
         sample_count: u32,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -152,7 +246,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: config.format,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             label: Some(label),
             view_formats: &[],
@@ -167,3 +261,97 @@ impl Texture {
         }
     }
 }
+
+/// Cuts the six faces, in the order +X, -X, +Y, -Y, +Z, -Z, out of a
+/// horizontal 4:3 or vertical 3:4 cross
+fn cross_faces(img: &image::DynamicImage, label: &str) -> Result<[image::DynamicImage; 6]> {
+    let (width, height) = img.dimensions();
+    // Cells of the cross holding +X, -X, +Y, -Y, +Z, -Z, by column and row
+    let (size, cells, rotate_back) = if width * 3 == height * 4 {
+        (
+            width / 4,
+            [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)],
+            false,
+        )
+    } else if width * 4 == height * 3 {
+        (
+            width / 3,
+            [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (1, 3)],
+            true,
+        )
+    } else {
+        bail!(
+            "{} is {}x{}, but a cube map cross must be 4:3 or 3:4",
+            label,
+            width,
+            height
+        );
+    };
+    let mut faces = cells.map(|(column, row)| img.crop_imm(column * size, row * size, size, size));
+    if rotate_back {
+        // The vertical cross has -Z upside down, below -Y
+        faces[5] = faces[5].rotate180();
+    }
+    Ok(faces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    /// Cross of 2x2 pixel cells, each coloured by its column and row, with
+    /// the top left pixel of every cell marked so rotations show
+    fn cross(columns: u32, rows: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(columns * 2, rows * 2, |x, y| {
+            let marked = x % 2 == 0 && y % 2 == 0;
+            Rgba([x as u8 / 2, y as u8 / 2, marked as u8, 255])
+        }))
+    }
+
+    /// Column and row of the cell a face was cut from, and whether it is
+    /// the right way up
+    fn cell(face: &DynamicImage) -> (u8, u8, bool) {
+        let Rgba([column, row, _, _]) = face.get_pixel(1, 1);
+        (column, row, face.get_pixel(0, 0)[2] == 1)
+    }
+
+    #[test]
+    fn horizontal_cross() {
+        let faces = cross_faces(&cross(4, 3), "test").unwrap();
+        let cells = faces.iter().map(cell).collect::<Vec<_>>();
+        assert_eq!(
+            cells,
+            vec![
+                (2, 1, true),
+                (0, 1, true),
+                (1, 0, true),
+                (1, 2, true),
+                (1, 1, true),
+                (3, 1, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn vertical_cross_turns_the_back_face() {
+        let faces = cross_faces(&cross(3, 4), "test").unwrap();
+        let cells = faces.iter().map(cell).collect::<Vec<_>>();
+        assert_eq!(
+            cells,
+            vec![
+                (2, 1, true),
+                (0, 1, true),
+                (1, 0, true),
+                (1, 2, true),
+                (1, 1, true),
+                (1, 3, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_shapes() {
+        assert!(cross_faces(&cross(3, 3), "test").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::analysis::{cluster_color, hsv_to_rgb, Schools};
+use crate::instance::Instance;
+use crate::simulation::Simulation;
+use crate::spatial::SpatialGrid;
+use cgmath::InnerSpace;
+use egui::{Align2, Color32, ComboBox, DragValue, FontId, Rect, Sense, Slider, Ui, Vec2};
+use serde::{Deserialize, Serialize};
+
+/// How much the random spawn tints show through the fish texture
+const RANDOM_STRENGTH: f32 = 0.05;
+/// Most schools listed in the cluster legend
+const LEGEND_SCHOOLS: usize = 8;
+
+/// What the colour of every fish shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TintMode {
+    /// The faint random tint every fish was spawned with
+    #[default]
+    Random,
+    Species,
+    Speed,
+    /// Compass direction of travel
+    Heading,
+    /// Number of neighbours within the interaction radius
+    Density,
+    /// School found by [`Schools`]
+    Cluster,
+    /// The selected fish and the neighbours it currently reacts to
+    Neighbourhood,
+}
+
+impl TintMode {
+    pub const ALL: [TintMode; 7] = [
+        TintMode::Random,
+        TintMode::Species,
+        TintMode::Speed,
+        TintMode::Heading,
+        TintMode::Density,
+        TintMode::Cluster,
+        TintMode::Neighbourhood,
+    ];
+}
+
+/// Recomputes the colour of every fish each frame from the simulation
+pub struct Tinting {
+    pub mode: TintMode,
+    /// How far the tint replaces the fish texture, except in random mode
+    pub strength: f32,
+    /// Fish whose neighbourhood is shown in [`TintMode::Neighbourhood`]
+    pub selected: usize,
+    /// Value mapped to the top of the colour ramp on the last update
+    max_value: f32,
+}
+
+impl Tinting {
+    pub fn new() -> Self {
+        Self {
+            mode: TintMode::default(),
+            strength: 0.6,
+            selected: 0,
+            max_value: 1.0,
+        }
+    }
+
+    /// Tints for every fish, padded to the `vec4` layout of the tint buffer
+    /// with the tint strength in the last component.
+    pub fn colors(
+        &mut self,
+        instances: &[Instance],
+        spawn_tints: &[[f32; 4]],
+        schools: &Schools,
+        simulation: &Simulation,
+    ) -> Vec<[f32; 4]> {
+        let radius = simulation.interaction_radius();
+        let colors: Vec<[f32; 3]> = match self.mode {
+            TintMode::Random => spawn_tints.iter().map(|&[r, g, b, _]| [r, g, b]).collect(),
+            TintMode::Species => instances.iter().map(|x| cluster_color(x.species)).collect(),
+            TintMode::Speed => {
+                // The constant-speed models only slow down while turning hard
+                let top = simulation
+                    .constant_speed()
+                    .unwrap_or(simulation.limits.max_speed);
+                self.max_value = top.max(f32::EPSILON);
+                instances
+                    .iter()
+                    .map(|x| ramp(x.velocity.magnitude() / self.max_value))
+                    .collect()
+            }
+            TintMode::Heading => instances
+                .iter()
+                .map(|x| hsv_to_rgb(heading(x.velocity.x, x.velocity.z), 0.8, 0.95))
+                .collect(),
+            TintMode::Density => {
+                let grid = SpatialGrid::new(radius, instances.iter().map(|x| x.position));
+                let counts = instances
+                    .iter()
+                    .map(|x| {
+                        let mut count = 0;
+                        grid.for_each_within(x.position, radius, |_| count += 1);
+                        // Don't count the fish itself
+                        count as f32 - 1.0
+                    })
+                    .collect::<Vec<_>>();
+                self.max_value = counts.iter().copied().fold(1.0, f32::max);
+                counts.iter().map(|x| ramp(x / self.max_value)).collect()
+            }
+            TintMode::Cluster => schools
+                .ids
+                .iter()
+                .map(|&id| cluster_color(schools.clusters[id].label))
+                .collect(),
+            TintMode::Neighbourhood => {
+                let selected = instances.get(self.selected);
+                instances
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| match selected {
+                        _ if i == self.selected => SELECTED,
+                        Some(boid) if simulation.perceives(boid, x) => NEIGHBOUR,
+                        _ => OTHER,
+                    })
+                    .collect()
+            }
+        };
+
+        let strength = match self.mode {
+            TintMode::Random => RANDOM_STRENGTH,
+            _ => self.strength,
+        };
+        colors
+            .into_iter()
+            .map(|[r, g, b]| [r, g, b, strength])
+            .collect()
+    }
+
+    pub(crate) fn ui(&mut self, ui: &mut Ui, boid_count: usize, simulation: &Simulation) {
+        ComboBox::from_label("Colour by")
+            .selected_text(format!("{:?}", self.mode))
+            .show_ui(ui, |ui| {
+                for mode in TintMode::ALL {
+                    ui.selectable_value(&mut self.mode, mode, format!("{:?}", mode));
+                }
+            });
+        if self.mode == TintMode::Random {
+            return;
+        }
+
+        ui.add(Slider::new(&mut self.strength, 0.0..=1.0).text("Strength"));
+        if self.mode == TintMode::Neighbourhood {
+            ui.add(
+                DragValue::new(&mut self.selected)
+                    .clamp_range(0..=boid_count.saturating_sub(1))
+                    .prefix("Fish: "),
+            );
+        }
+        ui.separator();
+
+        match self.mode {
+            TintMode::Random => {}
+            TintMode::Species => {
+                for (i, species) in simulation.species.iter().enumerate() {
+                    swatch(ui, cluster_color(i), &species.name);
+                }
+            }
+            TintMode::Speed => {
+                gradient(ui, ramp, "0", &format!("{:.1}", self.max_value));
+            }
+            TintMode::Heading => {
+                gradient(ui, |t| hsv_to_rgb(t, 0.8, 0.95), "+x", "+x");
+                ui.label("Hue turns from +x towards +z");
+            }
+            TintMode::Density => {
+                gradient(ui, ramp, "0", &format!("{:.0} neighbours", self.max_value));
+            }
+            TintMode::Cluster => {
+                for id in 0..LEGEND_SCHOOLS {
+                    swatch(ui, cluster_color(id), &format!("School {}", id));
+                }
+            }
+            TintMode::Neighbourhood => {
+                swatch(ui, SELECTED, "Selected");
+                swatch(ui, NEIGHBOUR, "Perceived by the selected fish");
+                swatch(ui, OTHER, "Others");
+            }
+        }
+    }
+}
+
+const SELECTED: [f32; 3] = [1.0, 1.0, 1.0];
+const NEIGHBOUR: [f32; 3] = [1.0, 0.55, 0.1];
+const OTHER: [f32; 3] = [0.15, 0.15, 0.2];
+
+/// Compass angle of a horizontal direction as a fraction of a full turn
+fn heading(x: f32, z: f32) -> f32 {
+    (z.atan2(x) / std::f32::consts::TAU).rem_euclid(1.0)
+}
+
+/// Perceptually ordered colour ramp from dark blue to yellow, after viridis
+fn ramp(t: f32) -> [f32; 3] {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.267, 0.005, 0.329],
+        [0.230, 0.322, 0.546],
+        [0.128, 0.567, 0.551],
+        [0.369, 0.789, 0.383],
+        [0.993, 0.906, 0.144],
+    ];
+    let x = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let i = (x as usize).min(STOPS.len() - 2);
+    let f = x - i as f32;
+    let [a, b] = [STOPS[i], STOPS[i + 1]];
+    [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * f)
+}
+
+fn to_color32(color: [f32; 3]) -> Color32 {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8);
+    Color32::from_rgb(r, g, b)
+}
+
+fn swatch(ui: &mut Ui, color: [f32; 3], label: &str) {
+    ui.horizontal(|ui| {
+        let (rect, _) = ui.allocate_exact_size(Vec2::splat(12.0), Sense::hover());
+        ui.painter().rect_filled(rect, 2.0, to_color32(color));
+        ui.label(label);
+    });
+}
+
+/// Horizontal bar showing `color` from 0 on the left to 1 on the right,
+/// labelled with the values at either end
+fn gradient(ui: &mut Ui, color: impl Fn(f32) -> [f32; 3], low: &str, high: &str) {
+    const SEGMENTS: usize = 64;
+    let size = Vec2::new(ui.available_width().min(240.0), 16.0);
+    let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+    let width = rect.width() / SEGMENTS as f32;
+    for i in 0..SEGMENTS {
+        let min = rect.min + Vec2::new(i as f32 * width, 0.0);
+        let segment = Rect::from_min_size(min, Vec2::new(width + 0.5, rect.height()));
+        let t = (i as f32 + 0.5) / SEGMENTS as f32;
+        ui.painter().rect_filled(segment, 0.0, to_color32(color(t)));
+    }
+
+    let (labels, _) = ui.allocate_exact_size(Vec2::new(size.x, 14.0), Sense::hover());
+    let font = FontId::proportional(12.0);
+    let text = ui.visuals().text_color();
+    let painter = ui.painter();
+    painter.text(labels.left_top(), Align2::LEFT_TOP, low, font.clone(), text);
+    painter.text(labels.right_top(), Align2::RIGHT_TOP, high, font, text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::SimulationModel;
+    use cgmath::{Vector3, Zero};
+
+    fn speed_colours(simulation: &Simulation, speed: f32) -> (f32, Vec<[f32; 4]>) {
+        let instances = [Instance::new(Vector3::zero(), Vector3::unit_x() * speed, 0)];
+        let mut tinting = Tinting::new();
+        tinting.mode = TintMode::Speed;
+        let colors = tinting.colors(&instances, &[[0.0; 4]], &Schools::new(), simulation);
+        (tinting.max_value, colors)
+    }
+
+    #[test]
+    fn speed_is_scaled_to_the_model() {
+        let mut simulation = Simulation::new(SimulationModel::Couzin);
+        let (top, _) = speed_colours(&simulation, 1.0);
+        assert_eq!(top, simulation.couzin.speed);
+
+        simulation.model = SimulationModel::Reynolds;
+        simulation.limits.min_speed = 0.0;
+        simulation.limits.max_speed = 0.0;
+        let (_, colors) = speed_colours(&simulation, 0.0);
+        assert!(colors[0].iter().all(|x| x.is_finite()), "{:?}", colors);
+    }
+}
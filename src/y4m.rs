@@ -0,0 +1,153 @@
+use image::RgbaImage;
+use std::io::{self, Write};
+
+/// Writes frames as an uncompressed YUV4MPEG2 stream, which encoders like
+/// ffmpeg and x264 read directly. Frames are converted to 4:2:0 chroma
+/// subsampled, limited range BT.601 YCbCr.
+pub(crate) struct Y4mWriter<W: Write> {
+    out: W,
+    width: u32,
+    height: u32,
+    // Planes are kept between frames to avoid reallocating them
+    y: Vec<u8>,
+    u: Vec<u8>,
+    v: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the stream header for frames of `width` by `height` shown at
+    /// `fps` frames per second
+    pub(crate) fn new(mut out: W, width: u32, height: u32, fps: f32) -> io::Result<Self> {
+        let (numerator, denominator) = frame_rate(fps);
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg",
+            width, height, numerator, denominator
+        )?;
+        Ok(Self {
+            out,
+            width,
+            height,
+            y: Vec::new(),
+            u: Vec::new(),
+            v: Vec::new(),
+        })
+    }
+
+    pub(crate) fn write_frame(&mut self, image: &RgbaImage) -> io::Result<()> {
+        if image.dimensions() != (self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame size differs from the stream header",
+            ));
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        // Odd sizes round up, the last chroma sample covering one column or row
+        let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+
+        self.y.clear();
+        for pixel in image.pixels() {
+            let [r, g, b, _] = pixel.0.map(|x| x as f32);
+            self.y
+                .push((16.0 + 0.2568 * r + 0.5041 * g + 0.0979 * b).round() as u8);
+        }
+
+        self.u.clear();
+        self.v.clear();
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                // Average the block of up to 2x2 pixels this sample covers
+                let (mut r, mut g, mut b, mut n) = (0.0, 0.0, 0.0, 0.0);
+                for y in 2 * cy..(2 * cy + 2).min(height) {
+                    for x in 2 * cx..(2 * cx + 2).min(width) {
+                        let [pr, pg, pb, _] = image.get_pixel(x as u32, y as u32).0;
+                        r += pr as f32;
+                        g += pg as f32;
+                        b += pb as f32;
+                        n += 1.0;
+                    }
+                }
+                let (r, g, b) = (r / n, g / n, b / n);
+                self.u
+                    .push((128.0 - 0.1482 * r - 0.2910 * g + 0.4392 * b).round() as u8);
+                self.v
+                    .push((128.0 + 0.4392 * r - 0.3678 * g - 0.0714 * b).round() as u8);
+            }
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&self.y)?;
+        self.out.write_all(&self.u)?;
+        self.out.write_all(&self.v)
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Frame rate as the fraction the header needs, exact for whole rates and
+/// to a thousandth of a frame otherwise, e.g. 29.97 as 29970:1000
+fn frame_rate(fps: f32) -> (u32, u32) {
+    if fps.fract() == 0.0 {
+        (fps as u32, 1)
+    } else {
+        ((fps * 1000.0).round() as u32, 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// Header, then the Y, U and V planes of every frame
+    fn encode(image: &RgbaImage) -> (String, Vec<u8>) {
+        let mut out = Vec::new();
+        let mut writer = Y4mWriter::new(&mut out, image.width(), image.height(), 30.0).unwrap();
+        writer.write_frame(image).unwrap();
+        writer.finish().unwrap();
+
+        let header_end = out.iter().position(|&x| x == b'\n').unwrap() + 1;
+        let header = String::from_utf8(out[..header_end].to_vec()).unwrap();
+        let frame = out[header_end..].strip_prefix(b"FRAME\n").unwrap().to_vec();
+        (header, frame)
+    }
+
+    #[test]
+    fn white_is_limited_range() {
+        let image = RgbaImage::from_pixel(4, 2, Rgba([255, 255, 255, 255]));
+        let (header, frame) = encode(&image);
+        assert_eq!(header, "YUV4MPEG2 W4 H2 F30:1 Ip A1:1 C420jpeg\n");
+        let (y, uv) = frame.split_at(8);
+        assert!(y.iter().all(|&x| x == 235), "{:?}", y);
+        assert_eq!(uv, [128; 4]);
+    }
+
+    #[test]
+    fn odd_sizes_round_the_chroma_up() {
+        // The last column is red and has a chroma sample to itself
+        let mut image = RgbaImage::from_pixel(3, 3, Rgba([0, 0, 0, 255]));
+        for y in 0..3 {
+            image.put_pixel(2, y, Rgba([255, 0, 0, 255]));
+        }
+        let (_, frame) = encode(&image);
+        assert_eq!(frame.len(), 9 + 2 * 4);
+        let (u, v) = frame[9..].split_at(4);
+        assert_eq!(u, [128, 90, 128, 90]);
+        assert_eq!(v, [128, 240, 128, 240]);
+    }
+
+    #[test]
+    fn rejects_frames_of_another_size() {
+        let mut writer = Y4mWriter::new(Vec::new(), 4, 4, 30.0).unwrap();
+        let image = RgbaImage::new(2, 2);
+        assert!(writer.write_frame(&image).is_err());
+    }
+
+    #[test]
+    fn fractional_frame_rates() {
+        assert_eq!(frame_rate(25.0), (25, 1));
+        assert_eq!(frame_rate(29.97), (29970, 1000));
+    }
+}